@@ -0,0 +1,302 @@
+//! Typed client for the query API of `indexer-balances`, the one served with `--api`
+
+mod models;
+
+pub use models::{
+    BalanceAt, BalanceEvent, BalancePoint, BalanceSource, Cause, Direction, EventsPage,
+    HistoryQuery, Order, Status, StreamQuery,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The indexer has rejected the request
+    #[error("{status}: {message}")]
+    Api {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("Invalid message in the event stream: {0}")]
+    Stream(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Client {
+    /// `base_url` is the address of the metrics server of the indexer, e.g. `http://localhost:3000`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// For the custom timeouts, proxies and so on
+    pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Page of the balance history of the account
+    pub async fn account_events(
+        &self,
+        account_id: &str,
+        query: &HistoryQuery,
+    ) -> Result<EventsPage> {
+        self.get(&format!("/accounts/{}/events", account_id), query)
+            .await
+    }
+
+    pub async fn transaction_events(&self, transaction_hash: &str) -> Result<Vec<BalanceEvent>> {
+        let list: models::EventsList = self
+            .get(&format!("/transactions/{}/events", transaction_hash), &())
+            .await?;
+        Ok(list.events)
+    }
+
+    pub async fn receipt_events(&self, receipt_id: &str) -> Result<Vec<BalanceEvent>> {
+        let list: models::EventsList = self
+            .get(&format!("/receipts/{}/events", receipt_id), &())
+            .await?;
+        Ok(list.events)
+    }
+
+    /// Balance at the end of the block or at the moment.
+    /// None if neither the index nor the archival node know it
+    pub async fn balance_at(
+        &self,
+        account_id: &str,
+        point: BalancePoint,
+    ) -> Result<Option<BalanceAt>> {
+        let query = match point {
+            BalancePoint::BlockHeight(height) => [("block_height", height)],
+            BalancePoint::Timestamp(timestamp) => [("timestamp", timestamp)],
+        };
+        match self
+            .get(&format!("/accounts/{}/balance", account_id), &query)
+            .await
+        {
+            Err(Error::Api { status, .. }) if status == reqwest::StatusCode::NOT_FOUND => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    /// Events stored by the indexer from now on, or after `from_event_index` if it's given
+    pub async fn stream_events(&self, query: &StreamQuery) -> Result<EventStream> {
+        Ok(EventStream {
+            response: self.send("/events/stream", query).await?,
+            buffer: vec![],
+            last_event_index: None,
+        })
+    }
+
+    async fn send(&self, path: &str, query: &impl serde::Serialize) -> Result<reqwest::Response> {
+        let response = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .send()
+            .await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let message = match response.json::<models::ErrorMessage>().await {
+            Ok(body) => body.error,
+            Err(_) => status.canonical_reason().unwrap_or_default().to_string(),
+        };
+        Err(Error::Api { status, message })
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &impl serde::Serialize,
+    ) -> Result<T> {
+        Ok(self.send(path, query).await?.json().await?)
+    }
+}
+
+/// Live events, see [`Client::stream_events`]
+pub struct EventStream {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    last_event_index: Option<u128>,
+}
+
+impl EventStream {
+    /// Waits for the next event. None when the indexer closes the stream,
+    /// reconnect with `from_event_index` set to [`EventStream::last_event_index`] to continue
+    pub async fn next(&mut self) -> Result<Option<BalanceEvent>> {
+        loop {
+            while let Some(end) = self.buffer.windows(2).position(|window| window == b"\n\n") {
+                let message: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                if let Some(event) = parse_message(&String::from_utf8_lossy(&message))? {
+                    self.last_event_index = Some(event.event_index);
+                    return Ok(Some(event));
+                }
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    pub fn last_event_index(&self) -> Option<u128> {
+        self.last_event_index
+    }
+}
+
+// Keep-alive comments have no data
+fn parse_message(message: &str) -> Result<Option<BalanceEvent>> {
+    let data: Vec<&str> = message
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(str::trim_start)
+        .collect();
+    if data.is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(&data.join("\n"))
+        .map(Some)
+        .map_err(|err| Error::Stream(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer};
+
+    use super::*;
+
+    fn event_json(event_index: &str) -> serde_json::Value {
+        serde_json::json!({
+            "event_index": event_index,
+            "block_timestamp": "1657000000000000000",
+            "block_height": 70000000,
+            "receipt_id": null,
+            "transaction_hash": "9x5DLYMapFSNSi5PnJMyJuM1Sx1TQ1YWZKFJNG7NUNWX",
+            "affected_account_id": "alice.near",
+            "involved_account_id": "bob.near",
+            "direction": "OUTBOUND",
+            "cause": "TRANSACTION",
+            "status": "SUCCESS",
+            "delta_nonstaked_amount": "-170141183460469231731687303715884105727",
+            "absolute_nonstaked_amount": "340282366920938463463374607431768211455",
+            "delta_staked_amount": "0",
+            "absolute_staked_amount": "0",
+        })
+    }
+
+    #[get("/accounts/{account_id}/events")]
+    async fn account_events(request: HttpRequest) -> HttpResponse {
+        // The query is echoed back to check it
+        HttpResponse::Ok().json(serde_json::json!({
+            "events": [event_json("16570000000000000000000000001")],
+            "next_cursor": request.query_string(),
+        }))
+    }
+
+    #[get("/accounts/{account_id}/balance")]
+    async fn account_balance(account_id: web::Path<String>) -> HttpResponse {
+        match account_id.as_str() {
+            "unknown.near" => {
+                HttpResponse::NotFound().json(serde_json::json!({"error": "Unknown"}))
+            }
+            _ => HttpResponse::BadRequest().json(serde_json::json!({"error": "Invalid point"})),
+        }
+    }
+
+    #[get("/events/stream")]
+    async fn stream_events() -> HttpResponse {
+        let body = format!(
+            ": keep-alive\n\nid: 1\nevent: balance_event\ndata: {}\n\nid: 2\nevent: balance_event\ndata: {}\n\n",
+            event_json("1"),
+            event_json("2"),
+        );
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .body(body)
+    }
+
+    fn start_mock_api() -> Client {
+        let server = HttpServer::new(|| {
+            App::new()
+                .service(account_events)
+                .service(account_balance)
+                .service(stream_events)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let client = Client::new(format!("http://{}/", server.addrs()[0]));
+        tokio::spawn(server.run());
+        client
+    }
+
+    #[tokio::test]
+    async fn decodes_account_events() {
+        let client = start_mock_api();
+        let page = client
+            .account_events(
+                "alice.near",
+                &HistoryQuery {
+                    limit: Some(10),
+                    order: Some(Order::Asc),
+                    cause: Some(Cause::ValidatorsReward),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            page.next_cursor.as_deref(),
+            Some("limit=10&order=asc&cause=VALIDATORS_REWARD")
+        );
+        let event = &page.events[0];
+        assert_eq!(event.event_index, 16570000000000000000000000001);
+        assert_eq!(event.delta_nonstaked_amount, -i128::MAX);
+        assert_eq!(event.absolute_nonstaked_amount, u128::MAX);
+        assert_eq!(
+            (event.direction, event.cause, event.status),
+            (Direction::Outbound, Cause::Transaction, Status::Success)
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_api_errors() {
+        let client = start_mock_api();
+        let unknown = client
+            .balance_at("unknown.near", BalancePoint::BlockHeight(1))
+            .await
+            .unwrap();
+        assert_eq!(unknown, None);
+
+        let err = client
+            .balance_at("alice.near", BalancePoint::Timestamp(1))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Api { status, message } if status.as_u16() == 400 && message == "Invalid point"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_event_stream() {
+        let client = start_mock_api();
+        let mut stream = client.stream_events(&StreamQuery::default()).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap().event_index, 1);
+        assert_eq!(stream.next().await.unwrap().unwrap().event_index, 2);
+        assert_eq!(stream.last_event_index(), Some(2));
+        assert!(stream.next().await.unwrap().is_none());
+    }
+}
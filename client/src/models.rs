@@ -0,0 +1,190 @@
+//! Types of the query API, see `/openapi.json` of the indexer.
+//! The big numbers are strings on the wire, here they are parsed into the integers
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Cause {
+    ValidatorsReward,
+    Transaction,
+    Receipt,
+    ContractReward,
+    /// The state change cause the indexer does not expect to change the balances, see `cause_payload`
+    Unknown,
+    /// The causes added to the indexer after this client was released
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Status {
+    Success,
+    Failure,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Change of the account balance, the same as the row of `near_balance_events`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BalanceEvent {
+    #[serde(with = "as_string")]
+    pub event_index: u128,
+    /// Nanoseconds
+    #[serde(with = "as_string")]
+    pub block_timestamp: u64,
+    pub block_height: u64,
+    pub receipt_id: Option<String>,
+    pub transaction_hash: Option<String>,
+    pub affected_account_id: String,
+    pub involved_account_id: Option<String>,
+    pub direction: Direction,
+    pub cause: Cause,
+    pub status: Status,
+    /// yoctoNEAR
+    #[serde(with = "as_string")]
+    pub delta_nonstaked_amount: i128,
+    #[serde(with = "as_string")]
+    pub absolute_nonstaked_amount: u128,
+    #[serde(with = "as_string")]
+    pub delta_staked_amount: i128,
+    #[serde(with = "as_string")]
+    pub absolute_staked_amount: u128,
+    /// The state change in JSON, for `UNKNOWN` cause only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause_payload: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EventsPage {
+    pub events: Vec<BalanceEvent>,
+    /// Pass it as `cursor` to get the next page, None on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct EventsList {
+    pub events: Vec<BalanceEvent>,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// Latest events first
+    Desc,
+    Asc,
+}
+
+/// Filters and pagination of the account history, all of them are optional
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryQuery {
+    /// `next_cursor` of the previous page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// 100 by default, up to 1000
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Order>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cause: Option<Cause>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    /// Inclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_block: Option<u64>,
+    /// Inclusive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_block: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalancePoint {
+    BlockHeight(u64),
+    /// Nanoseconds
+    Timestamp(u64),
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BalanceSource {
+    /// Taken from the indexed events
+    Indexed,
+    /// The account has no indexed history, the indexer asked the archival node
+    Rpc,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BalanceAt {
+    pub account_id: String,
+    /// For `Indexed`, the block of the latest event. For `Rpc`, the block the node was asked about
+    pub block_height: u64,
+    #[serde(with = "as_string")]
+    pub nonstaked_amount: u128,
+    #[serde(with = "as_string")]
+    pub staked_amount: u128,
+    pub source: BalanceSource,
+    /// The event the balance is taken from, for `Indexed` only
+    #[serde(default, with = "option_as_string")]
+    pub event_index: Option<u128>,
+}
+
+/// Filters of the live stream, all of them are optional
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamQuery {
+    /// Comma-separated list of the accounts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accounts: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cause: Option<Cause>,
+    /// yoctoNEAR, compared with the absolute values of the deltas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_amount: Option<String>,
+    /// The stored events after this one are sent first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_event_index: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct ErrorMessage {
+    pub error: String,
+}
+
+mod as_string {
+    pub fn serialize<T: std::fmt::Display, S: serde::Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+        D: serde::Deserializer<'de>,
+    {
+        let value: String = serde::Deserialize::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+mod option_as_string {
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+        D: serde::Deserializer<'de>,
+    {
+        let value: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+        value
+            .map(|value| value.parse().map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
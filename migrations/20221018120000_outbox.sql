@@ -0,0 +1,11 @@
+-- One row per stored block with events, written in the same transaction with the events.
+-- Enabled by `POSTGRES_OUTBOX`. Consumers may delete the rows they have processed
+CREATE TABLE near_balance_events_outbox
+(
+    block_height      numeric(20, 0) PRIMARY KEY,
+    block_timestamp   numeric(20, 0) NOT NULL,
+    first_event_index numeric(38, 0) NOT NULL,
+    last_event_index  numeric(38, 0) NOT NULL,
+    events_count      integer        NOT NULL,
+    created_at        timestamptz    NOT NULL DEFAULT now()
+);
@@ -0,0 +1,14 @@
+-- Webhook deliveries which failed all the attempts, written by `webhooks` sink
+CREATE TABLE webhook_dead_letters
+(
+    id                  bigserial PRIMARY KEY,
+    url                 text           NOT NULL,
+    event_index         numeric(38, 0) NOT NULL,
+    affected_account_id text           NOT NULL,
+    payload             text           NOT NULL,
+    error               text           NOT NULL,
+    attempts            integer        NOT NULL,
+    created_at          timestamptz    NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhook_dead_letters_url_idx ON webhook_dead_letters (url);
@@ -0,0 +1,14 @@
+-- Events matched by the alert rules with `table` output, written by `alerts` sink
+CREATE TABLE near_balance_alerts
+(
+    rule_name              text           NOT NULL,
+    event_index            numeric(38, 0) NOT NULL,
+    block_height           numeric(20, 0) NOT NULL,
+    affected_account_id    text           NOT NULL,
+    delta_nonstaked_amount numeric(40, 0) NOT NULL,
+    delta_staked_amount    numeric(40, 0) NOT NULL,
+    created_at             timestamptz    NOT NULL DEFAULT now(),
+    PRIMARY KEY (rule_name, event_index)
+);
+
+CREATE INDEX near_balance_alerts_affected_account_idx ON near_balance_alerts (affected_account_id);
@@ -0,0 +1,20 @@
+-- Current balance of every account, upserted by `postgres` sink in the same transaction with the events.
+-- Saves the scan of the whole `near_balance_events` to get the latest balances
+CREATE TABLE account_balances_latest
+(
+    account_id        text PRIMARY KEY,
+    nonstaked_amount  numeric(40, 0) NOT NULL,
+    staked_amount     numeric(40, 0) NOT NULL,
+    last_event_index  numeric(38, 0) NOT NULL,
+    last_block_height numeric(20, 0) NOT NULL
+);
+
+-- The accounts indexed before the table appeared
+INSERT INTO account_balances_latest
+SELECT DISTINCT ON (affected_account_id) affected_account_id,
+                                         absolute_nonstaked_amount,
+                                         absolute_staked_amount,
+                                         event_index,
+                                         block_height
+FROM near_balance_events
+ORDER BY affected_account_id, event_index DESC;
@@ -0,0 +1,24 @@
+-- Liquid balance of the account per UTC hour and day, for the charts.
+-- Updated by `postgres` sink with `POSTGRES_BUCKETS`, recomputed by `rebuild-buckets` command
+CREATE TABLE account_balance_buckets
+(
+    account_id        text           NOT NULL,
+    -- `hour` or `day`
+    bucket            text           NOT NULL,
+    -- UTC
+    bucket_start      timestamp      NOT NULL,
+    -- The balance before the first event of the bucket
+    open_amount       numeric(40, 0) NOT NULL,
+    close_amount      numeric(40, 0) NOT NULL,
+    min_amount        numeric(40, 0) NOT NULL,
+    max_amount        numeric(40, 0) NOT NULL,
+    -- Sum of the positive deltas
+    inbound_amount    numeric(40, 0) NOT NULL,
+    -- Sum of the negative deltas, by absolute value
+    outbound_amount   numeric(40, 0) NOT NULL,
+    first_event_index numeric(38, 0) NOT NULL,
+    last_event_index  numeric(38, 0) NOT NULL,
+    PRIMARY KEY (account_id, bucket, bucket_start)
+);
+
+CREATE INDEX account_balance_buckets_bucket_start_idx ON account_balance_buckets (bucket_start);
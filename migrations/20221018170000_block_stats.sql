@@ -0,0 +1,21 @@
+-- Network-wide summary of every block stored by `postgres` sink, written in the same transaction with its events
+CREATE TABLE near_balance_block_stats
+(
+    block_height            numeric(20, 0) PRIMARY KEY,
+    block_timestamp         numeric(20, 0) NOT NULL,
+    -- Sum of the positive liquid deltas
+    nonstaked_inflow        numeric(45, 0) NOT NULL,
+    -- Sum of the negative liquid deltas, by absolute value
+    nonstaked_outflow       numeric(45, 0) NOT NULL,
+    staked_inflow           numeric(45, 0) NOT NULL,
+    staked_outflow          numeric(45, 0) NOT NULL,
+    -- Both liquid and staked deltas of `VALIDATORS_REWARD` events
+    validators_reward       numeric(45, 0) NOT NULL,
+    -- Both liquid and staked deltas of `CONTRACT_REWARD` events
+    contract_reward         numeric(45, 0) NOT NULL,
+    affected_accounts_count integer        NOT NULL,
+    -- From the block header
+    total_supply            numeric(40, 0) NOT NULL
+);
+
+CREATE INDEX near_balance_block_stats_timestamp_idx ON near_balance_block_stats (block_timestamp);
@@ -0,0 +1,18 @@
+-- Blocks where the change of `total_supply` does not match the rewards and the burnt gas, see `--supply-check`
+CREATE TABLE near_balance_supply_mismatches
+(
+    block_height          numeric(20, 0) PRIMARY KEY,
+    block_timestamp       numeric(20, 0) NOT NULL,
+    -- From the headers of the previous block and this one
+    previous_total_supply numeric(40, 0) NOT NULL,
+    total_supply          numeric(40, 0) NOT NULL,
+    -- Both liquid and staked deltas of `VALIDATORS_REWARD` events, the minted tokens are expected to be here
+    validators_reward     numeric(45, 0) NOT NULL,
+    -- Sum of `balance_burnt` of the chunks included in the block
+    balance_burnt         numeric(40, 0) NOT NULL,
+    -- Sum of all the deltas of the block, for the investigation. Tokens attached to the receipts are not in any account,
+    -- so it differs from the supply change even when nothing is missed
+    events_delta          numeric(45, 0) NOT NULL,
+    -- total_supply - previous_total_supply - (validators_reward - balance_burnt)
+    difference            numeric(45, 0) NOT NULL
+);
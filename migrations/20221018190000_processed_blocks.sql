@@ -0,0 +1,10 @@
+-- Every block stored by `postgres` sink, written in the same transaction with its events.
+-- `prev_block_height` tells the skipped heights from the missed blocks, see `fill-gaps` command
+CREATE TABLE near_balance_processed_blocks
+(
+    block_height      numeric(20, 0) PRIMARY KEY,
+    -- NULL for the genesis
+    prev_block_height numeric(20, 0),
+    block_hash        text           NOT NULL,
+    processed_at      timestamptz    NOT NULL DEFAULT now()
+);
@@ -0,0 +1,5 @@
+-- Events extracted by a new version of the indexer, compared with `near_balance_events` by `compare` command
+CREATE TABLE near_balance_events_shadow
+(
+    LIKE near_balance_events INCLUDING ALL
+);
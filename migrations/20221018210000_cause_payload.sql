@@ -0,0 +1,3 @@
+-- The state change of the `UNKNOWN` cause events in JSON, see `--unexpected-cause-policy`
+ALTER TABLE near_balance_events ADD COLUMN cause_payload text;
+ALTER TABLE near_balance_events_shadow ADD COLUMN cause_payload text;
@@ -0,0 +1,7 @@
+-- The last event which fired the alerts, written by `alerts` sink.
+-- The events up to it are replayed after a restart, they should not fire the alerts again
+CREATE TABLE near_balance_alerts_cursor
+(
+    id          boolean        PRIMARY KEY DEFAULT true CHECK (id),
+    event_index numeric(38, 0) NOT NULL
+);
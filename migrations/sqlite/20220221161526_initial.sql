@@ -0,0 +1,24 @@
+-- Mirrors the Postgres schema. SQLite has no 40-digit numeric type,
+-- so event_index and the amounts are stored as text
+CREATE TABLE near_balance_events
+(
+    event_index               text    PRIMARY KEY,
+    block_timestamp           integer NOT NULL,
+    block_height              integer NOT NULL,
+    receipt_id                text,
+    transaction_hash          text,
+    affected_account_id       text    NOT NULL,
+    involved_account_id       text,
+    direction                 text    NOT NULL,
+    cause                     text    NOT NULL,
+    status                    text    NOT NULL,
+    delta_nonstaked_amount    text    NOT NULL,
+    absolute_nonstaked_amount text    NOT NULL,
+    delta_staked_amount       text    NOT NULL,
+    absolute_staked_amount    text    NOT NULL
+);
+
+CREATE INDEX near_balance_events_block_height_idx ON near_balance_events (block_height);
+CREATE INDEX near_balance_events_affected_account_idx ON near_balance_events (affected_account_id);
+CREATE INDEX near_balance_events_receipt_id_idx ON near_balance_events (receipt_id);
+CREATE INDEX near_balance_events_tx_hash_idx ON near_balance_events (transaction_hash);
@@ -0,0 +1,2 @@
+-- The state change of the `UNKNOWN` cause events in JSON
+ALTER TABLE near_balance_events ADD COLUMN cause_payload text;
@@ -0,0 +1,53 @@
+use actix_web::{get, web};
+
+use crate::api::{ApiError, ApiState};
+use crate::db_adapters::balance_at::{balance_at, BalanceAt, BalancePoint};
+
+#[derive(serde::Deserialize, utoipa::IntoParams, Debug)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct BalanceParams {
+    pub block_height: Option<u64>,
+    /// Nanoseconds
+    pub timestamp: Option<u64>,
+}
+
+/// Balance of the account at the end of the block or at the moment, exactly one of them is expected
+#[utoipa::path(
+    tag = "accounts",
+    params(("account_id", description = "Account ID"), BalanceParams),
+    responses(
+        (status = 200, body = BalanceAt),
+        (status = 400, body = ErrorMessage, description = "Both or none of the points are given"),
+        (status = 404, body = ErrorMessage, description = "The balance is unknown at this point, e.g. the timestamp is after the latest indexed block"),
+    )
+)]
+#[get("/accounts/{account_id}/balance")]
+pub(crate) async fn account_balance(
+    state: web::Data<ApiState>,
+    account_id: web::Path<String>,
+    params: web::Query<BalanceParams>,
+) -> Result<web::Json<BalanceAt>, ApiError> {
+    let point = match (params.block_height, params.timestamp) {
+        (Some(height), None) => BalancePoint::BlockHeight(height),
+        (None, Some(timestamp)) => BalancePoint::Timestamp(timestamp),
+        _ => {
+            return Err(ApiError::BadRequest(
+                "Exactly one of block_height and timestamp is expected".to_string(),
+            ))
+        }
+    };
+    let account_id = account_id
+        .parse()
+        .map_err(|err| ApiError::BadRequest(format!("Invalid account_id: {}", err)))?;
+
+    match balance_at(&state.pool, &state.json_rpc_client, &account_id, point)
+        .await
+        .map_err(ApiError::Internal)?
+    {
+        Some(balance) => Ok(web::Json(balance)),
+        None => Err(ApiError::NotFound(
+            "Neither the index nor the archival node know the balance at this point".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,299 @@
+use actix_web::{get, web};
+
+use crate::api::{ApiError, ApiState};
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::sinks::EventMessage;
+
+pub(crate) const DEFAULT_LIMIT: usize = 100;
+pub(crate) const MAX_LIMIT: usize = 1000;
+
+#[derive(
+    serde::Deserialize,
+    async_graphql::Enum,
+    utoipa::ToSchema,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Order {
+    /// Latest events first
+    #[default]
+    Desc,
+    Asc,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams, Debug, Default)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct HistoryParams {
+    /// `next_cursor` from the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub order: Order,
+    pub cause: Option<String>,
+    pub direction: Option<String>,
+    /// Inclusive
+    pub from_block: Option<u64>,
+    /// Inclusive
+    pub to_block: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, PartialEq)]
+pub(crate) struct EventsPage {
+    pub events: Vec<EventMessage>,
+    /// `event_index` of the last event, None if there are no more events
+    pub next_cursor: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, PartialEq)]
+pub(crate) struct EventsList {
+    pub events: Vec<EventMessage>,
+}
+
+/// Balance history of the account, paginated by `event_index`
+#[utoipa::path(
+    tag = "accounts",
+    params(("account_id", description = "Account ID"), HistoryParams),
+    responses(
+        (status = 200, body = EventsPage),
+        (status = 400, body = ErrorMessage, description = "Invalid limit or cursor"),
+    )
+)]
+#[get("/accounts/{account_id}/events")]
+pub(crate) async fn account_events(
+    state: web::Data<ApiState>,
+    account_id: web::Path<String>,
+    params: web::Query<HistoryParams>,
+) -> Result<web::Json<EventsPage>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(ApiError::BadRequest(format!(
+            "limit should be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+    let (query, args) = history_query(&account_id, &params, limit)?;
+    let mut events = fetch_events(&state.pool, &query, args).await?;
+
+    // We query one extra event to know if there is the next page
+    let next_cursor = if events.len() > limit {
+        events.truncate(limit);
+        events.last().map(|event| event.event_index.to_string())
+    } else {
+        None
+    };
+    Ok(web::Json(EventsPage {
+        events: events.iter().map(EventMessage::from).collect(),
+        next_cursor,
+    }))
+}
+
+/// Events caused by the transaction itself, without its receipts
+#[utoipa::path(
+    tag = "events",
+    params(("transaction_hash", description = "Transaction hash")),
+    responses((status = 200, body = EventsList))
+)]
+#[get("/transactions/{transaction_hash}/events")]
+pub(crate) async fn transaction_events(
+    state: web::Data<ApiState>,
+    transaction_hash: web::Path<String>,
+) -> Result<web::Json<EventsList>, ApiError> {
+    let events = fetch_events(
+        &state.pool,
+        "SELECT * FROM near_balance_events WHERE transaction_hash = $1 ORDER BY event_index",
+        vec![transaction_hash.into_inner()],
+    )
+    .await?;
+    Ok(web::Json(EventsList {
+        events: events.iter().map(EventMessage::from).collect(),
+    }))
+}
+
+#[utoipa::path(
+    tag = "events",
+    params(("receipt_id", description = "Receipt ID")),
+    responses((status = 200, body = EventsList))
+)]
+#[get("/receipts/{receipt_id}/events")]
+pub(crate) async fn receipt_events(
+    state: web::Data<ApiState>,
+    receipt_id: web::Path<String>,
+) -> Result<web::Json<EventsList>, ApiError> {
+    let events = fetch_events(
+        &state.pool,
+        "SELECT * FROM near_balance_events WHERE receipt_id = $1 ORDER BY event_index",
+        vec![receipt_id.into_inner()],
+    )
+    .await?;
+    Ok(web::Json(EventsList {
+        events: events.iter().map(EventMessage::from).collect(),
+    }))
+}
+
+// Numbers are passed as text and cast in the query, Postgres has no u64 and u128
+pub(crate) fn history_query(
+    account_id: &str,
+    params: &HistoryParams,
+    limit: usize,
+) -> Result<(String, Vec<String>), ApiError> {
+    let mut conditions = vec!["affected_account_id = $1".to_string()];
+    let mut args = vec![account_id.to_string()];
+    // `$` in the condition is replaced with the number of the placeholder
+    let mut add_condition = |condition: &str, arg: String| {
+        args.push(arg);
+        conditions.push(condition.replace('$', &format!("${}", args.len())));
+    };
+
+    if let Some(cause) = &params.cause {
+        add_condition("cause = $", cause.clone());
+    }
+    if let Some(direction) = &params.direction {
+        add_condition("direction = $", direction.clone());
+    }
+    if let Some(from_block) = params.from_block {
+        add_condition("block_height >= $::numeric", from_block.to_string());
+    }
+    if let Some(to_block) = params.to_block {
+        add_condition("block_height <= $::numeric", to_block.to_string());
+    }
+    if let Some(cursor) = &params.cursor {
+        let cursor = cursor
+            .parse::<u128>()
+            .map_err(|_| ApiError::BadRequest(format!("Invalid cursor {}", cursor)))?;
+        let condition = match params.order {
+            Order::Desc => "event_index < $::numeric",
+            Order::Asc => "event_index > $::numeric",
+        };
+        add_condition(condition, cursor.to_string());
+    }
+
+    let query = format!(
+        "SELECT * FROM near_balance_events WHERE {} ORDER BY event_index {} LIMIT {}",
+        conditions.join(" AND "),
+        match params.order {
+            Order::Desc => "DESC",
+            Order::Asc => "ASC",
+        },
+        limit + 1
+    );
+    Ok((query, args))
+}
+
+pub(crate) async fn fetch_events(
+    pool: &sqlx::PgPool,
+    query: &str,
+    args: Vec<String>,
+) -> Result<Vec<NearBalanceEvent>, ApiError> {
+    let mut query = sqlx::query_as::<_, NearBalanceEvent>(query);
+    for arg in args {
+        query = query.bind(arg);
+    }
+    Ok(query.fetch_all(pool).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+
+    use super::*;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    #[test]
+    fn builds_history_query() {
+        let params = HistoryParams {
+            cursor: Some("16570000000000000000000000000".to_string()),
+            cause: Some("RECEIPT".to_string()),
+            from_block: Some(70_000_000),
+            ..Default::default()
+        };
+        let (query, args) = history_query("alice.near", &params, 10).unwrap();
+        assert_eq!(
+            query,
+            "SELECT * FROM near_balance_events WHERE affected_account_id = $1 AND cause = $2 \
+             AND block_height >= $3::numeric AND event_index < $4::numeric \
+             ORDER BY event_index DESC LIMIT 11"
+        );
+        assert_eq!(
+            args,
+            vec![
+                "alice.near",
+                "RECEIPT",
+                "70000000",
+                "16570000000000000000000000000"
+            ]
+        );
+
+        let params = HistoryParams {
+            cursor: Some("1; DROP TABLE near_balance_events".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            history_query("alice.near", &params, 10),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn paginates_account_history() {
+        let (pool, _schema) = test_pool().await;
+        let header = block_header(1, 1_000_000_000);
+        let events: Vec<NearBalanceEvent> = (0..5)
+            .map(|i| balance_event(&header, i, "alice.near", 1, i + 1))
+            .collect();
+        crate::models::chunked_insert(&pool, &events, crate::RETRY_COUNT)
+            .await
+            .unwrap();
+
+        let app = actix_web::test::init_service(App::new().configure(|config| {
+            crate::api::configure(
+                config,
+                ApiState {
+                    pool: pool.clone(),
+                    json_rpc_client: near_jsonrpc_client::JsonRpcClient::connect(
+                        "http://127.0.0.1:1",
+                    ),
+                    live_events: crate::sinks::live::live_events(),
+                    graphql: None,
+                },
+            )
+        }))
+        .await;
+        let first: EventsPage = actix_web::test::call_and_read_body_json(
+            &app,
+            actix_web::test::TestRequest::get()
+                .uri("/accounts/alice.near/events?limit=3")
+                .to_request(),
+        )
+        .await;
+        let second: EventsPage = actix_web::test::call_and_read_body_json(
+            &app,
+            actix_web::test::TestRequest::get()
+                .uri(&format!(
+                    "/accounts/alice.near/events?limit=3&cursor={}",
+                    first.next_cursor.clone().unwrap()
+                ))
+                .to_request(),
+        )
+        .await;
+        let bad_request = actix_web::test::call_service(
+            &app,
+            actix_web::test::TestRequest::get()
+                .uri("/accounts/alice.near/events?limit=100000")
+                .to_request(),
+        )
+        .await;
+
+        let expected: Vec<EventMessage> = events.iter().rev().map(EventMessage::from).collect();
+        assert_eq!(first.events, expected[..3]);
+        assert_eq!(second.events, expected[3..]);
+        assert_eq!(second.next_cursor, None);
+        assert_eq!(bad_request.status(), 400);
+    }
+}
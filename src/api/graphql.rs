@@ -0,0 +1,320 @@
+use actix_web::{get, post, web, HttpResponse};
+use async_graphql::connection::{Connection, Edge};
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Schema};
+
+use crate::api::events::{
+    fetch_events, history_query, HistoryParams, Order, DEFAULT_LIMIT, MAX_LIMIT,
+};
+use crate::api::ApiError;
+use crate::sinks::EventMessage;
+
+/// Weight of the events of one transaction or receipt for the complexity limit, there are a few of them
+const LINKED_EVENTS_COMPLEXITY: usize = 10;
+
+pub(crate) type BalancesSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+pub(crate) fn schema(pool: sqlx::PgPool, config: &crate::configs::GraphqlConfig) -> BalancesSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(pool)
+        .limit_depth(config.graphql_max_depth)
+        .limit_complexity(config.graphql_max_complexity)
+        .finish()
+}
+
+#[post("/graphql")]
+pub(crate) async fn graphql(
+    schema: web::Data<BalancesSchema>,
+    request: web::Json<async_graphql::Request>,
+) -> web::Json<async_graphql::Response> {
+    web::Json(schema.execute(request.into_inner()).await)
+}
+
+/// GraphQL Playground to try the queries from the browser
+#[get("/graphql")]
+pub(crate) async fn playground() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(playground_source(GraphQLPlaygroundConfig::new("/graphql")))
+}
+
+// The details of the internal errors go to the logs, not to the clients
+fn graphql_error(err: ApiError) -> async_graphql::Error {
+    if let ApiError::Internal(err) = &err {
+        tracing::error!(target: crate::LOGGING_PREFIX, "GraphQL query failed: {}", err);
+    }
+    async_graphql::Error::new(err.to_string())
+}
+
+async fn linked_events(
+    ctx: &Context<'_>,
+    column: &str,
+    value: &str,
+) -> async_graphql::Result<Vec<EventMessage>> {
+    let events = fetch_events(
+        ctx.data::<sqlx::PgPool>()?,
+        &format!(
+            "SELECT * FROM near_balance_events WHERE {} = $1 ORDER BY event_index",
+            column
+        ),
+        vec![value.to_string()],
+    )
+    .await
+    .map_err(graphql_error)?;
+    Ok(events.iter().map(EventMessage::from).collect())
+}
+
+pub(crate) struct Query;
+
+#[Object]
+impl Query {
+    /// The account does not have to exist, its history is empty then
+    async fn account(&self, account_id: String) -> Account {
+        Account { account_id }
+    }
+
+    async fn transaction(&self, hash: String) -> Transaction {
+        Transaction { hash }
+    }
+
+    async fn receipt(&self, id: String) -> Receipt {
+        Receipt { id }
+    }
+}
+
+pub(crate) struct Account {
+    account_id: String,
+}
+
+#[Object]
+impl Account {
+    async fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    /// Balance history of the account, the same as `/accounts/{account_id}/events`.
+    /// `after` is `endCursor` of the previous page
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(complexity = "first.unwrap_or(DEFAULT_LIMIT) * child_complexity")]
+    async fn events(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<usize>,
+        after: Option<String>,
+        #[graphql(default)] order: Order,
+        cause: Option<String>,
+        direction: Option<String>,
+        #[graphql(desc = "Inclusive")] from_block: Option<u64>,
+        #[graphql(desc = "Inclusive")] to_block: Option<u64>,
+    ) -> async_graphql::Result<Connection<String, EventMessage>> {
+        let limit = first.unwrap_or(DEFAULT_LIMIT);
+        if limit == 0 || limit > MAX_LIMIT {
+            return Err(async_graphql::Error::new(format!(
+                "first should be between 1 and {}",
+                MAX_LIMIT
+            )));
+        }
+        let params = HistoryParams {
+            cursor: after,
+            limit: Some(limit),
+            order,
+            cause,
+            direction,
+            from_block,
+            to_block,
+        };
+        let (query, args) =
+            history_query(&self.account_id, &params, limit).map_err(graphql_error)?;
+        let mut events = fetch_events(ctx.data::<sqlx::PgPool>()?, &query, args)
+            .await
+            .map_err(graphql_error)?;
+
+        // We query one extra event to know if there is the next page
+        let has_next_page = events.len() > limit;
+        events.truncate(limit);
+        let mut connection = Connection::new(params.cursor.is_some(), has_next_page);
+        connection.edges.extend(
+            events
+                .iter()
+                .map(|event| Edge::new(event.event_index.to_string(), EventMessage::from(event))),
+        );
+        Ok(connection)
+    }
+}
+
+pub(crate) struct Transaction {
+    hash: String,
+}
+
+#[Object]
+impl Transaction {
+    async fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    #[graphql(complexity = "LINKED_EVENTS_COMPLEXITY * child_complexity")]
+    async fn events(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<EventMessage>> {
+        linked_events(ctx, "transaction_hash", &self.hash).await
+    }
+}
+
+pub(crate) struct Receipt {
+    id: String,
+}
+
+#[Object]
+impl Receipt {
+    async fn id(&self) -> &str {
+        &self.id
+    }
+
+    #[graphql(complexity = "LINKED_EVENTS_COMPLEXITY * child_complexity")]
+    async fn events(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<EventMessage>> {
+        linked_events(ctx, "receipt_id", &self.id).await
+    }
+}
+
+/// Links from the event to the other objects
+#[ComplexObject]
+impl EventMessage {
+    async fn affected_account(&self) -> Account {
+        Account {
+            account_id: self.affected_account_id.clone(),
+        }
+    }
+
+    async fn involved_account(&self) -> Option<Account> {
+        self.involved_account_id
+            .clone()
+            .map(|account_id| Account { account_id })
+    }
+
+    async fn transaction(&self) -> Option<Transaction> {
+        self.transaction_hash
+            .clone()
+            .map(|hash| Transaction { hash })
+    }
+
+    async fn receipt(&self) -> Option<Receipt> {
+        self.receipt_id.clone().map(|id| Receipt { id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+
+    use super::*;
+    use crate::models::balance_changes::NearBalanceEvent;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    fn config(max_depth: usize, max_complexity: usize) -> crate::configs::GraphqlConfig {
+        crate::configs::GraphqlConfig {
+            graphql: true,
+            graphql_max_depth: max_depth,
+            graphql_max_complexity: max_complexity,
+        }
+    }
+
+    fn errors(response: &async_graphql::Response) -> Vec<String> {
+        response
+            .errors
+            .iter()
+            .map(|error| error.message.clone())
+            .collect()
+    }
+
+    // The limits are checked before anything is read from the DB
+    #[tokio::test]
+    async fn rejects_expensive_queries() {
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let schema = schema(pool, &config(4, 500));
+
+        let deep = schema
+            .execute(
+                "{ account(accountId: \"alice.near\") { events { edges { node {
+                    transaction { hash } } } } } }",
+            )
+            .await;
+        assert_eq!(errors(&deep), vec!["Query is nested too deep."]);
+
+        let large = schema
+            .execute(
+                "{ account(accountId: \"alice.near\") { events(first: 1000) {
+                    edges { cursor } } } }",
+            )
+            .await;
+        assert_eq!(errors(&large), vec!["Query is too complex."]);
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn paginates_account_events() {
+        let (pool, _schema) = test_pool().await;
+        let header = block_header(1, 1_000_000_000);
+        let events: Vec<NearBalanceEvent> = (0..3)
+            .map(|i| balance_event(&header, i, "alice.near", 1, i + 1))
+            .collect();
+        crate::models::chunked_insert(&pool, &events, crate::RETRY_COUNT)
+            .await
+            .unwrap();
+
+        let schema = schema(pool.clone(), &config(8, 10_000));
+        let app = actix_web::test::init_service(
+            App::new().app_data(web::Data::new(schema)).service(graphql),
+        )
+        .await;
+        let query = |after: &str| {
+            let request = actix_web::test::TestRequest::post()
+                .uri("/graphql")
+                .set_json(serde_json::json!({
+                    "query": "query($after: String) {
+                        account(accountId: \"alice.near\") {
+                            events(first: 2, order: ASC, after: $after) {
+                                edges { node { eventIndex absoluteNonstakedAmount affectedAccount { accountId } } }
+                                pageInfo { hasNextPage endCursor }
+                            }
+                        }
+                    }",
+                    "variables": { "after": if after.is_empty() { None } else { Some(after) } },
+                }))
+                .to_request();
+            actix_web::test::call_and_read_body_json(&app, request)
+        };
+        let first: serde_json::Value = query("").await;
+        let end_cursor = first["data"]["account"]["events"]["pageInfo"]["endCursor"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let second: serde_json::Value = query(&end_cursor).await;
+
+        let amounts = |page: &serde_json::Value| {
+            page["data"]["account"]["events"]["edges"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|edge| {
+                    edge["node"]["absoluteNonstakedAmount"]
+                        .as_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(amounts(&first), vec!["1", "2"]);
+        assert_eq!(
+            first["data"]["account"]["events"]["pageInfo"]["hasNextPage"],
+            true
+        );
+        assert_eq!(end_cursor, events[1].event_index.to_string());
+        assert_eq!(amounts(&second), vec!["3"]);
+        assert_eq!(
+            second["data"]["account"]["events"]["edges"][0]["node"]["affectedAccount"]["accountId"],
+            "alice.near"
+        );
+        assert_eq!(
+            second["data"]["account"]["events"]["pageInfo"]["hasNextPage"],
+            false
+        );
+    }
+}
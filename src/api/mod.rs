@@ -0,0 +1,86 @@
+use actix_web::{web, HttpResponse, ResponseError};
+
+pub(crate) mod balances;
+pub(crate) mod events;
+pub(crate) mod graphql;
+pub(crate) mod openapi;
+pub(crate) mod stream;
+
+/// Shared by all the handlers
+#[derive(Clone)]
+pub(crate) struct ApiState {
+    pub pool: sqlx::PgPool,
+    pub json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+    /// Blocks stored by the indexer, for `/events/stream`
+    pub live_events: crate::sinks::live::LiveEvents,
+    /// `/graphql` is served if it's set
+    pub graphql: Option<graphql::BalancesSchema>,
+}
+
+/// Read-only endpoints over `near_balance_events`, served next to `/metrics`
+pub(crate) fn configure(config: &mut web::ServiceConfig, state: ApiState) {
+    if let Some(schema) = &state.graphql {
+        config
+            .app_data(web::Data::new(schema.clone()))
+            .service(graphql::graphql)
+            .service(graphql::playground);
+    }
+    config
+        .app_data(web::Data::new(state))
+        .service(openapi::openapi_json)
+        .service(balances::account_balance)
+        .service(events::account_events)
+        .service(events::transaction_events)
+        .service(events::receipt_events)
+        .service(stream::stream_events);
+}
+
+/// Body of the error responses
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug)]
+pub(crate) struct ErrorMessage {
+    pub error: String,
+}
+
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    Internal(anyhow::Error),
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::BadRequest(message) | ApiError::NotFound(message) => {
+                write!(f, "{}", message)
+            }
+            // The details go to the logs, not to the clients
+            ApiError::Internal(_) => write!(f, "Internal error"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            ApiError::BadRequest(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
+            ApiError::Internal(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let ApiError::Internal(err) = self {
+            tracing::error!(target: crate::LOGGING_PREFIX, "API request failed: {}", err);
+        }
+        HttpResponse::build(self.status_code()).json(ErrorMessage {
+            error: self.to_string(),
+        })
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        ApiError::Internal(err.into())
+    }
+}
@@ -0,0 +1,99 @@
+use actix_web::{get, web};
+use utoipa::OpenApi;
+
+use crate::api::{balances, events, stream};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        events::account_events,
+        balances::account_balance,
+        events::transaction_events,
+        events::receipt_events,
+        stream::stream_events,
+    ),
+    components(schemas(
+        crate::sinks::EventMessage,
+        events::EventsPage,
+        events::EventsList,
+        events::Order,
+        crate::db_adapters::balance_at::BalanceAt,
+        crate::db_adapters::balance_at::BalanceSource,
+        crate::api::ErrorMessage,
+    )),
+    tags(
+        (name = "accounts", description = "Balance history and balances of the accounts"),
+        (name = "events", description = "Balance events by their origin"),
+    )
+)]
+pub(crate) struct ApiDoc;
+
+/// OpenAPI description of the query API, generated from the handlers
+#[get("/openapi.json")]
+pub(crate) async fn openapi_json() -> web::Json<utoipa::openapi::OpenApi> {
+    web::Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+
+    use super::*;
+    use crate::test_utils::{balance_event, block_header};
+
+    #[actix_web::test]
+    async fn serves_openapi_document() {
+        let app = actix_web::test::init_service(App::new().service(openapi_json)).await;
+        let document: serde_json::Value = actix_web::test::call_and_read_body_json(
+            &app,
+            actix_web::test::TestRequest::get()
+                .uri("/openapi.json")
+                .to_request(),
+        )
+        .await;
+
+        let paths: Vec<&String> = document["paths"].as_object().unwrap().keys().collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/accounts/{account_id}/balance",
+                "/accounts/{account_id}/events",
+                "/events/stream",
+                "/receipts/{receipt_id}/events",
+                "/transactions/{transaction_hash}/events",
+            ]
+        );
+        assert!(document["components"]["schemas"]["EventMessage"].is_object());
+    }
+
+    // The client crate mirrors the JSON of the API, it should not drift away
+    #[test]
+    fn client_decodes_event_messages() {
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let mut event = balance_event(&header, 3, "alice.near", -5, 10);
+        event.delta_staked_amount = 7.into();
+        let message = serde_json::to_string(&crate::sinks::EventMessage::from(&event)).unwrap();
+
+        let decoded: indexer_balances_client::BalanceEvent =
+            serde_json::from_str(&message).unwrap();
+        assert_eq!(
+            decoded.event_index.to_string(),
+            event.event_index.to_string()
+        );
+        assert_eq!(decoded.block_timestamp, 1_657_000_000_000_000_000);
+        assert_eq!(decoded.affected_account_id, "alice.near");
+        assert_eq!(
+            decoded.direction,
+            indexer_balances_client::Direction::Outbound
+        );
+        assert_eq!(
+            (
+                decoded.delta_nonstaked_amount,
+                decoded.absolute_nonstaked_amount
+            ),
+            (-5, 10)
+        );
+        assert_eq!(decoded.delta_staked_amount, 7);
+        assert_eq!(serde_json::to_string(&decoded).unwrap(), message);
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use bigdecimal::BigDecimal;
+use futures::StreamExt;
+
+use crate::api::{ApiError, ApiState};
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::sinks::EventMessage;
+
+const REPLAY_PAGE_SIZE: usize = 1000;
+const KEEP_ALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+#[derive(serde::Deserialize, utoipa::IntoParams, Debug, Default)]
+#[serde(deny_unknown_fields)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct StreamParams {
+    /// Comma-separated list of `affected_account_id`
+    pub accounts: Option<String>,
+    pub cause: Option<String>,
+    /// yoctoNEAR, compared with the absolute values of the deltas
+    pub min_amount: Option<String>,
+    /// Send the stored events after this one first. `Last-Event-ID` header does the same
+    pub from_event_index: Option<String>,
+}
+
+struct Filter {
+    accounts: Option<HashSet<String>>,
+    cause: Option<String>,
+    min_amount: Option<BigDecimal>,
+}
+
+impl Filter {
+    fn matches(&self, event: &NearBalanceEvent) -> bool {
+        self.accounts.as_ref().map_or(true, |accounts| {
+            accounts.contains(&event.affected_account_id)
+        }) && self
+            .cause
+            .as_ref()
+            .map_or(true, |cause| cause == &event.cause)
+            && self.min_amount.as_ref().map_or(true, |min_amount| {
+                &event.delta_nonstaked_amount.abs() >= min_amount
+                    || &event.delta_staked_amount.abs() >= min_amount
+            })
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<BigDecimal, ApiError> {
+    // Only the plain non-negative integers, the same as `event_index` and the amounts are
+    value
+        .parse::<u128>()
+        .ok()
+        .and_then(|_| BigDecimal::from_str(value).ok())
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid {} {}", name, value)))
+}
+
+/// Server-sent events with the new balance events, right after they are stored.
+/// Every message has `event_index` as its ID, so the reconnecting clients continue where they stopped
+#[utoipa::path(
+    tag = "events",
+    params(
+        StreamParams,
+        ("Last-Event-ID" = Option<String>, Header, description = "Set by the browsers on reconnect"),
+    ),
+    responses(
+        (status = 200, body = String, content_type = "text/event-stream",
+            description = "`balance_event` messages with `EventMessage` JSON as data"),
+        (status = 400, body = ErrorMessage, description = "Invalid min_amount or from_event_index"),
+    )
+)]
+#[get("/events/stream")]
+pub(crate) async fn stream_events(
+    state: web::Data<ApiState>,
+    request: HttpRequest,
+    params: web::Query<StreamParams>,
+) -> Result<HttpResponse, ApiError> {
+    let filter = Filter {
+        accounts: params
+            .accounts
+            .as_ref()
+            .map(|accounts| accounts.split(',').map(str::to_string).collect()),
+        cause: params.cause.clone(),
+        min_amount: params
+            .min_amount
+            .as_ref()
+            .map(|min_amount| parse_number("min_amount", min_amount))
+            .transpose()?,
+    };
+    let last_event_id = request
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok());
+    let from_event_index = params
+        .from_event_index
+        .as_deref()
+        .or(last_event_id)
+        .map(|from_event_index| parse_number("from_event_index", from_event_index))
+        .transpose()?;
+
+    // We subscribe before reading the stored events, so nothing is lost in between
+    let live_events = state.live_events.subscribe();
+    let (sender, receiver) = tokio::sync::mpsc::channel::<web::Bytes>(100);
+    let pool = state.pool.clone();
+    actix_web::rt::spawn(async move {
+        if let Err(err) = forward_events(pool, filter, from_event_index, live_events, sender).await
+        {
+            tracing::debug!(target: crate::LOGGING_PREFIX, "Event stream is closed: {}", err);
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((actix_web::http::header::CACHE_CONTROL, "no-cache"))
+        .streaming(
+            tokio_stream::wrappers::ReceiverStream::new(receiver).map(Ok::<_, actix_web::Error>),
+        ))
+}
+
+// Stops when the client is gone, or when it is too slow and misses the blocks
+async fn forward_events(
+    pool: sqlx::PgPool,
+    filter: Filter,
+    from_event_index: Option<BigDecimal>,
+    mut live_events: tokio::sync::broadcast::Receiver<Arc<Vec<NearBalanceEvent>>>,
+    sender: tokio::sync::mpsc::Sender<web::Bytes>,
+) -> anyhow::Result<()> {
+    let last_replayed = match from_event_index {
+        Some(from_event_index) => Some(replay(&pool, &filter, from_event_index, &sender).await?),
+        None => None,
+    };
+
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+    loop {
+        tokio::select! {
+            events = live_events.recv() => {
+                for event in events?.iter() {
+                    let is_replayed = last_replayed
+                        .as_ref()
+                        .map_or(false, |last_replayed| &event.event_index <= last_replayed);
+                    if !is_replayed && filter.matches(event) {
+                        sender.send(to_message(event)?).await?;
+                    }
+                }
+            }
+            _ = keep_alive.tick() => {
+                sender.send(web::Bytes::from_static(b": keep-alive\n\n")).await?;
+            }
+        }
+    }
+}
+
+/// Sends the stored events after the given one, returns the latest event it has seen
+async fn replay(
+    pool: &sqlx::PgPool,
+    filter: &Filter,
+    from_event_index: BigDecimal,
+    sender: &tokio::sync::mpsc::Sender<web::Bytes>,
+) -> anyhow::Result<BigDecimal> {
+    let mut conditions = vec!["event_index > $1::numeric"];
+    if filter.accounts.is_some() {
+        conditions.push("affected_account_id = ANY($2)");
+    }
+    if filter.cause.is_some() {
+        conditions.push(if filter.accounts.is_some() {
+            "cause = $3"
+        } else {
+            "cause = $2"
+        });
+    }
+    let query = format!(
+        "SELECT * FROM near_balance_events WHERE {} ORDER BY event_index LIMIT {}",
+        conditions.join(" AND "),
+        REPLAY_PAGE_SIZE
+    );
+
+    let mut cursor = from_event_index;
+    loop {
+        let mut page_query = sqlx::query_as::<_, NearBalanceEvent>(&query).bind(cursor.to_string());
+        if let Some(accounts) = &filter.accounts {
+            page_query = page_query.bind(accounts.iter().cloned().collect::<Vec<_>>());
+        }
+        if let Some(cause) = &filter.cause {
+            page_query = page_query.bind(cause.clone());
+        }
+        let events = page_query.fetch_all(pool).await?;
+
+        for event in events.iter().filter(|event| filter.matches(event)) {
+            sender.send(to_message(event)?).await?;
+        }
+        if let Some(last) = events.last() {
+            cursor = last.event_index.clone();
+        }
+        if events.len() < REPLAY_PAGE_SIZE {
+            return Ok(cursor);
+        }
+    }
+}
+
+fn to_message(event: &NearBalanceEvent) -> anyhow::Result<web::Bytes> {
+    Ok(format!(
+        "id: {}\nevent: balance_event\ndata: {}\n\n",
+        event.event_index,
+        serde_json::to_string(&EventMessage::from(event))?
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpServer};
+
+    use super::*;
+    use crate::sinks::EventSink;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    fn start_api(pool: sqlx::PgPool, live_events: crate::sinks::live::LiveEvents) -> String {
+        let state = ApiState {
+            pool,
+            json_rpc_client: near_jsonrpc_client::JsonRpcClient::connect("http://127.0.0.1:1"),
+            live_events,
+            graphql: None,
+        };
+        let server = HttpServer::new(move || {
+            App::new().configure(|config| crate::api::configure(config, state.clone()))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        tokio::spawn(server.run());
+        url
+    }
+
+    // Reads the stream until the given number of events arrives
+    async fn read_events(response: &mut reqwest::Response, count: usize) -> Vec<String> {
+        let mut body = String::new();
+        while body.matches("data: ").count() < count {
+            let chunk = tokio::time::timeout(std::time::Duration::from_secs(10), response.chunk())
+                .await
+                .expect("Timed out waiting for the events")
+                .unwrap()
+                .expect("The stream is closed");
+            body += std::str::from_utf8(&chunk).unwrap();
+        }
+        body.split("\n\n")
+            .filter(|message| message.starts_with("id: "))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn streams_filtered_live_events() {
+        let live_events = crate::sinks::live::live_events();
+        // Nothing is read from the DB without `from_event_index`
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let url = start_api(pool, live_events.clone());
+
+        let mut response = reqwest::get(format!(
+            "{}/events/stream?accounts=alice.near,carol.near&min_amount=10",
+            url
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        let sink = crate::sinks::live::LiveSink::new(live_events);
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let events = vec![
+            balance_event(&header, 0, "alice.near", -5, 10),
+            balance_event(&header, 1, "bob.near", 100, 100),
+            balance_event(&header, 2, "alice.near", -20, 0),
+            balance_event(&header, 3, "carol.near", 10, 10),
+        ];
+        sink.store_block(&header, &events).await.unwrap();
+
+        let messages = read_events(&mut response, 2).await;
+        let expected = |event: &NearBalanceEvent| {
+            String::from_utf8(to_message(event).unwrap().to_vec())
+                .unwrap()
+                .trim_end()
+                .to_string()
+        };
+        assert_eq!(messages, [expected(&events[2]), expected(&events[3])]);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_parameters() {
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let url = start_api(pool, crate::sinks::live::live_events());
+        let response = reqwest::get(format!("{}/events/stream?min_amount=a_lot", url))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn resumes_from_event_index() {
+        let (pool, _schema) = test_pool().await;
+        let stored = block_header(1, 1_000_000_000);
+        let stored_events: Vec<NearBalanceEvent> = (0..3)
+            .map(|i| balance_event(&stored, i, "alice.near", 1, i + 1))
+            .collect();
+        crate::models::chunked_insert(&pool, &stored_events, crate::RETRY_COUNT)
+            .await
+            .unwrap();
+
+        let live_events = crate::sinks::live::live_events();
+        let url = start_api(pool.clone(), live_events.clone());
+        let client = reqwest::Client::new();
+        let mut response = client
+            .get(format!("{}/events/stream?accounts=alice.near", url))
+            .header("Last-Event-ID", stored_events[0].event_index.to_string())
+            .send()
+            .await
+            .unwrap();
+        let replayed = read_events(&mut response, 2).await;
+
+        let live = block_header(2, 2_000_000_000);
+        let live_event = balance_event(&live, 0, "alice.near", 1, 4);
+        // The block which was stored and replayed already is not sent again
+        crate::sinks::live::LiveSink::new(live_events.clone())
+            .store_block(&stored, &stored_events)
+            .await
+            .unwrap();
+        crate::sinks::live::LiveSink::new(live_events)
+            .store_block(&live, std::slice::from_ref(&live_event))
+            .await
+            .unwrap();
+        let streamed = read_events(&mut response, 1).await;
+
+        let ids = |messages: &[String]| {
+            messages
+                .iter()
+                .map(|message| message.lines().next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(&replayed),
+            vec![
+                format!("id: {}", stored_events[1].event_index),
+                format!("id: {}", stored_events[2].event_index),
+            ]
+        );
+        assert_eq!(
+            ids(&streamed),
+            vec![format!("id: {}", live_event.event_index)]
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use crate::db_adapters::balance_at::{balance_at, BalancePoint};
+
+pub(crate) async fn run(
+    args: &crate::configs::BalanceAtArgs,
+    opts: &crate::configs::Opts,
+) -> anyhow::Result<()> {
+    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+    let json_rpc_client = near_jsonrpc_client::JsonRpcClient::connect(&opts.near_archival_rpc_url);
+
+    let point = match (args.block_height, args.timestamp) {
+        (Some(height), _) => BalancePoint::BlockHeight(height),
+        (None, Some(timestamp)) => BalancePoint::Timestamp(timestamp),
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+    match balance_at(&pool, &json_rpc_client, &args.account_id.parse()?, point).await? {
+        Some(balance) => println!("{}", serde_json::to_string_pretty(&balance)?),
+        None => anyhow::bail!(
+            "Neither the index nor the archival node know the balance of {} at {:?}",
+            args.account_id,
+            point
+        ),
+    }
+    Ok(())
+}
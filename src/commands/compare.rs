@@ -0,0 +1,51 @@
+use crate::db_adapters::shadow_diff::diff_range;
+
+pub(crate) async fn run(
+    args: &crate::configs::CompareArgs,
+    opts: &crate::configs::Opts,
+) -> anyhow::Result<()> {
+    if args.from_block > args.to_block {
+        anyhow::bail!("--from-block should not be after --to-block");
+    }
+    let database_url = std::env::var("DATABASE_URL")?;
+    let pool = sqlx::PgPool::connect(&database_url).await?;
+
+    if !args.skip_indexing {
+        // The previous runs over the same range should not affect the result
+        sqlx::query(
+            "DELETE FROM near_balance_events_shadow WHERE block_height BETWEEN $1::numeric AND $2::numeric",
+        )
+        .bind(args.from_block.to_string())
+        .bind(args.to_block.to_string())
+        .execute(&pool)
+        .await?;
+        let sinks = crate::sinks::Sinks::new(Box::new(
+            crate::sinks::shadow::ShadowSink::connect(&database_url).await?,
+        ));
+        let indexed =
+            super::index_blocks(opts, &sinks, args.from_block, args.to_block, &[]).await?;
+        tracing::info!(
+            target: crate::LOGGING_PREFIX,
+            "Indexed {} blocks to the shadow table",
+            indexed
+        );
+    }
+
+    let summary = diff_range(&pool, args.from_block, args.to_block, |diff| {
+        println!("{}", serde_json::to_string(&diff)?);
+        Ok(())
+    })
+    .await?;
+    tracing::info!(
+        target: crate::LOGGING_PREFIX,
+        "Compared {} events: {} missing, {} extra, {} changed",
+        summary.compared,
+        summary.missing,
+        summary.extra,
+        summary.changed
+    );
+    if summary.differences() > 0 {
+        anyhow::bail!("Found {} differences", summary.differences());
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use num_traits::ToPrimitive;
+use sqlx::FromRow;
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::sinks::parquet::{day_start_nanosec, unfinished_files, ParquetWriter};
+
+const PAGE_SIZE: usize = 10_000;
+
+pub(crate) async fn run(
+    args: &crate::configs::ExportArgs,
+    opts: &crate::configs::Opts,
+) -> anyhow::Result<()> {
+    if args.output_dir.exists() && !unfinished_files(&args.output_dir)?.is_empty() {
+        anyhow::bail!(
+            "{} has unfinished parquet files, it looks like the directory of the running `parquet` sink",
+            args.output_dir.display()
+        );
+    }
+    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+    let mut writer = ParquetWriter::new(&crate::configs::ParquetSinkConfig {
+        parquet_dir: args.output_dir.clone(),
+        ..opts.parquet_sink.clone()
+    })?;
+
+    let from_timestamp = args.from_date.map_or(0, day_start_nanosec);
+    let to_timestamp = match args.to_date {
+        Some(day) => {
+            day_start_nanosec(
+                day.succ_opt()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid date {}", day))?,
+            ) - 1
+        }
+        None => u64::MAX,
+    };
+    let query = format!(
+        "SELECT * FROM near_balance_events
+        WHERE event_index > $1::numeric
+            AND block_height BETWEEN $2::numeric AND $3::numeric
+            AND block_timestamp BETWEEN $4::numeric AND $5::numeric
+        ORDER BY event_index
+        LIMIT {}",
+        PAGE_SIZE
+    );
+    let mut substitution_items = vec![
+        "-1".to_string(),
+        args.from_block.unwrap_or(0).to_string(),
+        args.to_block.unwrap_or(u64::MAX).to_string(),
+        from_timestamp.to_string(),
+        to_timestamp.to_string(),
+    ];
+
+    let mut exported = 0;
+    loop {
+        let rows = crate::models::select_retry_or_panic(
+            &pool,
+            &query,
+            &substitution_items,
+            crate::RETRY_COUNT,
+        )
+        .await?;
+        let events = rows
+            .iter()
+            .map(NearBalanceEvent::from_row)
+            .collect::<Result<Vec<_>, _>>()?;
+        let last_event_index = match events.last() {
+            Some(event) => event.event_index.to_string(),
+            None => break,
+        };
+
+        // The page may end in the middle of the block, the writer is fine with that
+        let mut block_start = 0;
+        for i in 1..=events.len() {
+            if i == events.len() || events[i].block_height != events[block_start].block_height {
+                let block_events = &events[block_start..i];
+                writer.write_block(
+                    to_u64(&block_events[0].block_height)?,
+                    to_u64(&block_events[0].block_timestamp)?,
+                    block_events,
+                )?;
+                block_start = i;
+            }
+        }
+
+        exported += events.len();
+        tracing::info!(
+            target: crate::LOGGING_PREFIX,
+            "Exported {} events, last event_index {}",
+            exported,
+            last_event_index
+        );
+        substitution_items[0] = last_event_index;
+    }
+    writer.finish()?;
+
+    tracing::info!(
+        target: crate::LOGGING_PREFIX,
+        "Export is finished, {} events in total",
+        exported
+    );
+    Ok(())
+}
+
+fn to_u64(value: &bigdecimal::BigDecimal) -> anyhow::Result<u64> {
+    value
+        .to_u64()
+        .ok_or_else(|| anyhow::anyhow!("{} is expected to be u64", value))
+}
@@ -0,0 +1,63 @@
+use crate::db_adapters::gaps::find_gaps;
+
+pub(crate) async fn run(
+    args: &crate::configs::FillGapsArgs,
+    opts: &crate::configs::Opts,
+) -> anyhow::Result<()> {
+    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+    let from_block = args.from_block.unwrap_or(0);
+    let to_block = args.to_block.unwrap_or(u64::MAX);
+
+    let gaps = find_gaps(&pool, from_block, to_block).await?;
+    for gap in &gaps {
+        tracing::info!(
+            target: crate::LOGGING_PREFIX,
+            "Blocks are missing between {} and {}",
+            gap.after_block,
+            gap.before_block
+        );
+    }
+    if args.dry_run || gaps.is_empty() {
+        return Ok(());
+    }
+
+    let sinks = crate::sinks::Sinks::postgres_only(opts).await?;
+    for gap in &gaps {
+        // Every gap is indexed with its own cache, the balances come from the node at the start of the gap
+        let indexed =
+            super::index_blocks(opts, &sinks, gap.after_block + 1, gap.before_block - 1, &[])
+                .await?;
+        tracing::info!(
+            target: crate::LOGGING_PREFIX,
+            "Indexed {} blocks between {} and {}",
+            indexed,
+            gap.after_block,
+            gap.before_block
+        );
+    }
+
+    // The buckets skip the events older than the ones they already have, so the days are recomputed
+    if opts.postgres_sink.postgres_buckets {
+        let mut days = std::collections::BTreeSet::new();
+        for gap in &gaps {
+            days.extend(
+                crate::db_adapters::balance_buckets::days_of_blocks(
+                    &pool,
+                    gap.after_block + 1,
+                    gap.before_block - 1,
+                )
+                .await?,
+            );
+        }
+        for day in days {
+            crate::db_adapters::balance_buckets::rebuild_day(&pool, day).await?;
+            tracing::info!(target: crate::LOGGING_PREFIX, "Rebuilt balance buckets of {}", day);
+        }
+    }
+
+    let remaining = find_gaps(&pool, from_block, to_block).await?;
+    if !remaining.is_empty() {
+        anyhow::bail!("Blocks are still missing: {:?}", remaining);
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use crate::configs::{Command, Opts};
+
+pub(crate) mod balance_at;
+pub(crate) mod compare;
+pub(crate) mod export;
+pub(crate) mod fill_gaps;
+pub(crate) mod rebuild_buckets;
+pub(crate) mod reindex;
+pub(crate) mod verify;
+
+pub(crate) async fn run(command: &Command, opts: &Opts) -> anyhow::Result<()> {
+    match command {
+        Command::Export(args) => export::run(args, opts).await,
+        Command::BalanceAt(args) => balance_at::run(args, opts).await,
+        Command::RebuildBuckets(args) => rebuild_buckets::run(args).await,
+        Command::Verify(args) => verify::run(args, opts).await,
+        Command::FillGaps(args) => fill_gaps::run(args, opts).await,
+        Command::Reindex(args) => reindex::run(args, opts).await,
+        Command::Compare(args) => compare::run(args, opts).await,
+    }
+}
+
+/// Indexes the blocks from `from_block` to `to_block` inclusive with a fresh balance cache.
+/// The balances of `seed_accounts` are put to the cache from the state before the first block of the range,
+/// the other ones are taken from the archival node when they change for the first time.
+/// Fails if the stream stops before `to_block`
+pub(crate) async fn index_blocks(
+    opts: &Opts,
+    sinks: &crate::sinks::Sinks,
+    from_block: u64,
+    to_block: u64,
+    seed_accounts: &[near_lake_framework::near_indexer_primitives::types::AccountId],
+) -> anyhow::Result<usize> {
+    let balances_cache = crate::BalanceCache::new(100_000, opts.balance_cache_check_percent);
+    let json_rpc_client = near_jsonrpc_client::JsonRpcClient::connect(&opts.near_archival_rpc_url);
+    let (lake_handle, mut stream) =
+        near_lake_framework::streamer(opts.to_lake_config(from_block).await);
+
+    let mut indexed = 0;
+    let mut last_height = None;
+    while let Some(streamer_message) = stream.recv().await {
+        let block_header = &streamer_message.block.header;
+        if block_header.height > to_block {
+            last_height = Some(block_header.height);
+            break;
+        }
+        if indexed == 0 {
+            for account_id in seed_accounts {
+                crate::db_adapters::balance_changes::get_balance_retriable(
+                    account_id,
+                    block_header,
+                    &balances_cache,
+                    &json_rpc_client,
+                )
+                .await?;
+            }
+        }
+        crate::db_adapters::balance_changes::store_balance_changes(
+            sinks,
+            &streamer_message.shards,
+            block_header,
+            &balances_cache,
+            &json_rpc_client,
+            &opts.unexpected_causes,
+            None,
+        )
+        .await?;
+        indexed += 1;
+        last_height = Some(block_header.height);
+        tracing::info!(
+            target: crate::LOGGING_PREFIX,
+            "Indexed block {}",
+            block_header.height
+        );
+        if block_header.height == to_block {
+            break;
+        }
+    }
+    if last_height.map_or(false, |height| height >= to_block) {
+        lake_handle.abort();
+        return Ok(indexed);
+    }
+    // The stream is closed before the end of the range, the callers must not treat the range as indexed
+    let lake_error = match lake_handle.await {
+        Ok(Ok(())) => "the stream is finished".to_string(),
+        Ok(Err(err)) => err.to_string(),
+        Err(err) => err.to_string(),
+    };
+    anyhow::bail!(
+        "Lake stream stopped after block {:?}, before block {}: {}",
+        last_height,
+        to_block,
+        lake_error
+    )
+}
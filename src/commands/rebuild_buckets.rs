@@ -0,0 +1,16 @@
+pub(crate) async fn run(args: &crate::configs::RebuildBucketsArgs) -> anyhow::Result<()> {
+    if args.from_date > args.to_date {
+        anyhow::bail!("--from-date should not be after --to-date");
+    }
+    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+
+    for day in args
+        .from_date
+        .iter_days()
+        .take_while(|day| day <= &args.to_date)
+    {
+        crate::db_adapters::balance_buckets::rebuild_day(&pool, day).await?;
+        tracing::info!(target: crate::LOGGING_PREFIX, "Rebuilt balance buckets of {}", day);
+    }
+    Ok(())
+}
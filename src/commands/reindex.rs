@@ -0,0 +1,58 @@
+use crate::db_adapters::reindex::{latest_block_height, RangeTransaction};
+use crate::sinks::parquet::utc_day;
+
+pub(crate) async fn run(
+    args: &crate::configs::ReindexArgs,
+    opts: &crate::configs::Opts,
+) -> anyhow::Result<()> {
+    if args.from > args.to {
+        anyhow::bail!("--from should not be after --to");
+    }
+    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+    // The live indexer writes the same rows otherwise. After a restart it replays the last blocks,
+    // and its inserts would wait for the deleted rows of the range until the end of the reindexing
+    match latest_block_height(&pool).await? {
+        Some(latest) if args.to + crate::models::RESTART_OVERLAP_BLOCKS < latest => {}
+        Some(latest) => anyhow::bail!(
+            "The range should end more than {} blocks below the latest indexed block {}",
+            crate::models::RESTART_OVERLAP_BLOCKS,
+            latest
+        ),
+        None => anyhow::bail!("Nothing is indexed yet"),
+    }
+
+    let range = std::sync::Arc::new(RangeTransaction::begin(&pool, args.from, args.to).await?);
+    let seed_accounts = range
+        .affected_accounts()
+        .iter()
+        .map(|account_id| account_id.parse())
+        .collect::<Result<Vec<_>, _>>()?;
+    tracing::info!(
+        target: crate::LOGGING_PREFIX,
+        "Deleted the range, {} accounts had events there",
+        seed_accounts.len()
+    );
+    let sinks = crate::sinks::Sinks::new(Box::new(range.clone()));
+    // Fails if the stream stops in the middle, then the transaction is rolled back on drop with the deletion
+    let indexed = super::index_blocks(opts, &sinks, args.from, args.to, &seed_accounts).await?;
+    let timestamps = range.commit().await?;
+    tracing::info!(
+        target: crate::LOGGING_PREFIX,
+        "Replaced blocks {}..={}, {} blocks indexed",
+        args.from,
+        args.to,
+        indexed
+    );
+
+    // The buckets are aggregated from the events, so they are rebuilt after the events are replaced
+    if let (true, Some((first, last))) = (opts.postgres_sink.postgres_buckets, timestamps) {
+        for day in utc_day(first)
+            .iter_days()
+            .take_while(|day| day <= &utc_day(last))
+        {
+            crate::db_adapters::balance_buckets::rebuild_day(&pool, day).await?;
+            tracing::info!(target: crate::LOGGING_PREFIX, "Rebuilt balance buckets of {}", day);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use crate::db_adapters::continuity::{find_breaks, ContinuityBreak, Verdict, VerifyRange};
+
+#[derive(serde::Serialize)]
+struct Report<'a> {
+    #[serde(flatten)]
+    continuity_break: &'a ContinuityBreak,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verdict: Option<Verdict>,
+}
+
+pub(crate) async fn run(
+    args: &crate::configs::VerifyArgs,
+    opts: &crate::configs::Opts,
+) -> anyhow::Result<()> {
+    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+    let json_rpc_client = near_jsonrpc_client::JsonRpcClient::connect(&opts.near_archival_rpc_url);
+    let range = VerifyRange {
+        account_id: args.account_id.clone(),
+        from_block: args.from_block,
+        to_block: args.to_block,
+    };
+
+    let count = find_breaks(&pool, &range, |continuity_break| {
+        let json_rpc_client = &json_rpc_client;
+        async move {
+            let verdict = if args.rpc {
+                Some(continuity_break.check_with_rpc(json_rpc_client).await?)
+            } else {
+                None
+            };
+            let report = Report {
+                continuity_break: &continuity_break,
+                verdict,
+            };
+            println!("{}", serde_json::to_string(&report)?);
+            Ok(())
+        }
+    })
+    .await?;
+    if count > 0 {
+        anyhow::bail!("Found {} continuity breaks", count);
+    }
+    tracing::info!(target: crate::LOGGING_PREFIX, "No continuity breaks found");
+    Ok(())
+}
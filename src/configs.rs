@@ -21,12 +21,366 @@ pub(crate) struct Opts {
     pub start_block_height: Option<u64>,
     #[clap(long, short, env)]
     pub near_archival_rpc_url: String,
+    /// Compare N percent of the balances taken from the cache with `view_account` at the previous block.
+    /// The balances changed earlier in the same block are not compared.
+    /// Divergences are logged, counted in `indexer_balances_cache_mismatches_total` and evicted from the cache
+    #[clap(long, env, default_value_t = 0, value_parser = clap::value_parser!(u8).range(..=100))]
+    pub balance_cache_check_percent: u8,
     // Chain ID: testnet or mainnet, used for NEAR Lake initialization
     #[clap(long, env)]
     pub chain_id: String,
     /// Port to enable metrics service
     #[clap(long, short, env, default_value_t = 3000)]
     pub port: u16,
+    /// Serve the read-only query API on the metrics port, requires `DATABASE_URL`
+    #[clap(long, env)]
+    pub api: bool,
+    #[clap(flatten)]
+    pub graphql: GraphqlConfig,
+    /// Comma-separated list of the destinations for balance events.
+    /// `postgres` requires `DATABASE_URL`, `sqlite` writes to `SQLITE_DATABASE_URL`,
+    /// `jsonl` and `csv` are configured with `--file-*` options,
+    /// `parquet` with `--parquet-*` options, `clickhouse` with `--clickhouse-*` options,
+    /// `kafka` with `--kafka-*` options, `webhooks` with `--webhook*` options and `DATABASE_URL` for the dead letters,
+    /// `alerts` with `--alert-rules`, `shadow` writes to `near_balance_events_shadow` in `DATABASE_URL`
+    #[clap(
+        long,
+        env,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "postgres"
+    )]
+    pub sinks: Vec<crate::sinks::SinkKind>,
+    /// SQLite database for `sqlite` sink, the file is created if it does not exist
+    #[clap(long, env, default_value = "sqlite://balance_events.sqlite")]
+    pub sqlite_database_url: String,
+    #[clap(flatten)]
+    pub postgres_sink: PostgresSinkConfig,
+    #[clap(flatten)]
+    pub supply_check: SupplyCheckConfig,
+    #[clap(flatten)]
+    pub unexpected_causes: UnexpectedCausesConfig,
+    #[clap(flatten)]
+    pub file_sink: FileSinkConfig,
+    #[clap(flatten)]
+    pub parquet_sink: ParquetSinkConfig,
+    #[clap(flatten)]
+    pub clickhouse_sink: ClickHouseSinkConfig,
+    #[clap(flatten)]
+    pub kafka_sink: KafkaSinkConfig,
+    #[clap(flatten)]
+    pub webhooks: WebhooksConfig,
+    /// JSON file with the rules for `alerts` sink, it also needs `DATABASE_URL`. Webhook outputs use `--webhook-*` settings
+    #[clap(long, env, default_value = "alert_rules.json")]
+    pub alert_rules: std::path::PathBuf,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// One-off jobs. Without the command, the indexer is started
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Export `near_balance_events` from Postgres to Parquet files in `--output-dir`,
+    /// see `--parquet-row-group-size` and `--parquet-max-rows-per-file`
+    Export(ExportArgs),
+    /// Print the balance of the account at the end of the block or at the moment.
+    /// Uses `near_balance_events` from `DATABASE_URL`, or the archival node if the account has no indexed history
+    BalanceAt(BalanceAtArgs),
+    /// Recompute `account_balance_buckets` of the UTC days from `near_balance_events` in `DATABASE_URL`.
+    /// Each day is replaced in its own transaction
+    RebuildBuckets(RebuildBucketsArgs),
+    /// Check that every event of `near_balance_events` in `DATABASE_URL` continues the previous event of the account:
+    /// previous absolute amount + delta = absolute amount. The breaks are printed as JSON lines
+    Verify(VerifyArgs),
+    /// Find the blocks missed by `postgres` sink using `near_balance_processed_blocks` in `DATABASE_URL`,
+    /// and index them again. Every block is written in its own transaction
+    FillGaps(FillGapsArgs),
+    /// Index the blocks again and replace their rows in `DATABASE_URL` in one transaction,
+    /// e.g. after a fix of the extraction. The range should end more than 1000 blocks below the latest indexed block
+    Reindex(ReindexArgs),
+    /// Index the blocks to `near_balance_events_shadow` in `DATABASE_URL` and print how they differ
+    /// from `near_balance_events`, as JSON lines. Run it with the new version of the indexer before deploying it
+    Compare(CompareArgs),
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(group(clap::ArgGroup::new("point").required(true).args(&["block-height", "timestamp"])))]
+pub(crate) struct BalanceAtArgs {
+    #[clap(long)]
+    pub account_id: String,
+    #[clap(long)]
+    pub block_height: Option<u64>,
+    /// Nanoseconds
+    #[clap(long)]
+    pub timestamp: Option<u64>,
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct RebuildBucketsArgs {
+    /// First UTC day, inclusive. Format: YYYY-MM-DD
+    #[clap(long)]
+    pub from_date: chrono::NaiveDate,
+    /// Last UTC day, inclusive. Format: YYYY-MM-DD
+    #[clap(long)]
+    pub to_date: chrono::NaiveDate,
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct VerifyArgs {
+    /// Check only this account
+    #[clap(long)]
+    pub account_id: Option<String>,
+    /// First block to check, inclusive. Its events are not compared with the earlier ones
+    #[clap(long)]
+    pub from_block: Option<u64>,
+    /// Last block to check, inclusive
+    #[clap(long)]
+    pub to_block: Option<u64>,
+    /// Ask the archival node about the balances at both sides of every break to find which row is wrong
+    #[clap(long)]
+    pub rpc: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct FillGapsArgs {
+    /// First block to check, inclusive. The blocks before the first processed one are not checked
+    #[clap(long)]
+    pub from_block: Option<u64>,
+    /// Last block to check, inclusive
+    #[clap(long)]
+    pub to_block: Option<u64>,
+    /// Only print the gaps
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ReindexArgs {
+    /// First block, inclusive
+    #[clap(long)]
+    pub from: u64,
+    /// Last block, inclusive
+    #[clap(long)]
+    pub to: u64,
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct CompareArgs {
+    /// First block, inclusive
+    #[clap(long)]
+    pub from_block: u64,
+    /// Last block, inclusive
+    #[clap(long)]
+    pub to_block: u64,
+    /// Compare the shadow events which are already stored, e.g. by `shadow` sink
+    #[clap(long)]
+    pub skip_indexing: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct ExportArgs {
+    /// Directory for Parquet files, partitioned the same way as `parquet` sink does it.
+    /// It should not be the directory of the running sink
+    #[clap(long)]
+    pub output_dir: std::path::PathBuf,
+    /// First block to export, inclusive
+    #[clap(long)]
+    pub from_block: Option<u64>,
+    /// Last block to export, inclusive
+    #[clap(long)]
+    pub to_block: Option<u64>,
+    /// First UTC day to export, inclusive. Format: YYYY-MM-DD
+    #[clap(long)]
+    pub from_date: Option<chrono::NaiveDate>,
+    /// Last UTC day to export, inclusive. Format: YYYY-MM-DD
+    #[clap(long)]
+    pub to_date: Option<chrono::NaiveDate>,
+}
+
+/// Settings for `parquet` sink, `export` command takes all of them except the directory
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct ParquetSinkConfig {
+    /// Directory for Parquet files, partitioned by UTC day of the block: `date=YYYY-MM-DD/`
+    #[clap(long, env, default_value = "balance_events_parquet")]
+    pub parquet_dir: std::path::PathBuf,
+    /// Number of rows buffered in memory before they are written as a row group
+    #[clap(long, env, default_value_t = 100_000)]
+    pub parquet_row_group_size: usize,
+    /// Start a new file in the same day partition when the current one reaches N rows.
+    /// The file becomes visible only when it's closed
+    #[clap(long, env, default_value_t = 1_000_000)]
+    pub parquet_max_rows_per_file: usize,
+}
+
+/// Settings for `postgres` sink
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct PostgresSinkConfig {
+    /// Write a row to `near_balance_events_outbox` for every stored block with events
+    #[clap(long, env)]
+    pub postgres_outbox: bool,
+    /// `NOTIFY` the channel about every stored block with events. The payload is the outbox row in JSON
+    #[clap(long, env)]
+    pub postgres_notify_channel: Option<String>,
+    /// Keep hourly and daily balance aggregates in `account_balance_buckets` up to date
+    #[clap(long, env)]
+    pub postgres_buckets: bool,
+    /// Compare the events of every block with `account_balances_latest` before storing them.
+    /// Breaks of the continuity are logged and counted in `indexer_balances_continuity_breaks_total`
+    #[clap(long, env)]
+    pub postgres_verify_continuity: bool,
+}
+
+/// Settings of the total supply reconciliation
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct SupplyCheckConfig {
+    /// Check that the change of `total_supply` in every block is the validators reward minus the burnt gas.
+    /// Mismatches are logged and counted in `indexer_balances_supply_mismatches_total`
+    #[clap(long, env)]
+    pub supply_check: bool,
+    /// Also write the mismatches to `near_balance_supply_mismatches` in `DATABASE_URL`, requires `--supply-check`
+    #[clap(long, env)]
+    pub supply_check_table: bool,
+}
+
+/// What to do with the balance changes of the state change causes we do not expect to change the balances:
+/// `not_writable_to_disk`, `initial_state`, `action_receipt_processing_started`, `updated_delayed_receipts`,
+/// `postponed_receipt` and `resharding`
+#[derive(clap::Args, Debug, Clone, Default)]
+pub(crate) struct UnexpectedCausesConfig {
+    /// `fail` stops the indexing. `skip` logs the change and counts it in `indexer_balances_skipped_causes_total`,
+    /// the absolute amounts stay right but the deltas of the account do not add up. `record` writes an `UNKNOWN` cause event with the state change in `cause_payload`
+    #[clap(long, env, value_enum, default_value = "fail")]
+    pub unexpected_cause_policy: UnexpectedCausePolicy,
+    /// Comma-separated list of `cause=policy` overriding `--unexpected-cause-policy`, e.g. `postponed_receipt=record,resharding=skip`
+    #[clap(long, env, value_delimiter = ',', value_parser = parse_cause_policy)]
+    pub unexpected_cause_policies: Vec<(String, UnexpectedCausePolicy)>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum UnexpectedCausePolicy {
+    #[default]
+    Fail,
+    Skip,
+    Record,
+}
+
+const UNEXPECTED_CAUSES: [&str; 6] = [
+    "not_writable_to_disk",
+    "initial_state",
+    "action_receipt_processing_started",
+    "updated_delayed_receipts",
+    "postponed_receipt",
+    "resharding",
+];
+
+fn parse_cause_policy(value: &str) -> anyhow::Result<(String, UnexpectedCausePolicy)> {
+    let (cause, policy) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected `cause=policy`, got `{}`", value))?;
+    if !UNEXPECTED_CAUSES.contains(&cause) {
+        anyhow::bail!(
+            "Unknown cause `{}`, expected one of {}",
+            cause,
+            UNEXPECTED_CAUSES.join(", ")
+        );
+    }
+    let policy = clap::ValueEnum::from_str(policy, false).map_err(|err| anyhow::anyhow!(err))?;
+    Ok((cause.to_string(), policy))
+}
+
+impl UnexpectedCausesConfig {
+    /// `cause` is the `type` of the state change cause in JSON
+    pub(crate) fn policy(&self, cause: &str) -> UnexpectedCausePolicy {
+        self.unexpected_cause_policies
+            .iter()
+            .rev()
+            .find(|(overridden, _)| overridden == cause)
+            .map_or(self.unexpected_cause_policy, |(_, policy)| *policy)
+    }
+}
+
+/// Settings for `clickhouse` sink
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct ClickHouseSinkConfig {
+    /// ClickHouse HTTP interface
+    #[clap(long, env, default_value = "http://localhost:8123")]
+    pub clickhouse_url: String,
+    /// Database to create `near_balance_events` table in, it should already exist
+    #[clap(long, env, default_value = "default")]
+    pub clickhouse_database: String,
+    #[clap(long, env)]
+    pub clickhouse_user: Option<String>,
+    #[clap(long, env)]
+    pub clickhouse_password: Option<String>,
+    /// Events are buffered across the blocks until N of them are collected
+    #[clap(long, env, default_value_t = 10_000)]
+    pub clickhouse_batch_size: usize,
+    /// ... or until the oldest buffered event waits for N seconds
+    #[clap(long, env, default_value_t = 10)]
+    pub clickhouse_flush_interval_secs: u64,
+}
+
+/// Settings for `kafka` sink
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct KafkaSinkConfig {
+    /// Comma-separated list of the brokers
+    #[clap(long, env, default_value = "localhost:9092")]
+    pub kafka_brokers: String,
+    #[clap(long, env, default_value = "near_balance_events")]
+    pub kafka_topic: String,
+    /// Indexing stops if the event is not acknowledged by the broker during N seconds
+    #[clap(long, env, default_value_t = 300)]
+    pub kafka_message_timeout_secs: u64,
+}
+
+/// Settings for `/graphql`
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct GraphqlConfig {
+    /// Serve GraphQL at `/graphql` next to the query API, requires `--api`
+    #[clap(long, env)]
+    pub graphql: bool,
+    /// Queries with deeper nesting are rejected
+    #[clap(long, env, default_value_t = 8)]
+    pub graphql_max_depth: usize,
+    /// Queries are rejected if they may return more fields, the lists count as `first` times their fields
+    #[clap(long, env, default_value_t = 10_000)]
+    pub graphql_max_complexity: usize,
+}
+
+/// Settings for `webhooks` sink
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct WebhooksConfig {
+    /// JSON file with the list of `{"url": "...", "secret": "...", "accounts": ["..."]}`
+    #[clap(long, env, default_value = "webhooks.json")]
+    pub webhooks_watchlist: std::path::PathBuf,
+    /// Delivery goes to `webhook_dead_letters` table after N failed attempts
+    #[clap(long, env, default_value_t = 5)]
+    pub webhook_max_attempts: usize,
+    #[clap(long, env, default_value_t = 10)]
+    pub webhook_timeout_secs: u64,
+    /// Number of deliveries sent at the same time
+    #[clap(long, env, default_value_t = 16)]
+    pub webhook_concurrency: usize,
+    /// Indexing waits when N deliveries are not sent yet
+    #[clap(long, env, default_value_t = 10_000)]
+    pub webhook_queue_size: usize,
+}
+
+/// Settings for `jsonl` and `csv` sinks
+#[derive(clap::Args, Debug, Clone)]
+pub(crate) struct FileSinkConfig {
+    /// Directory to put the files with balance events to
+    #[clap(long, env, default_value = "balance_events")]
+    pub file_sink_dir: std::path::PathBuf,
+    /// Start a new file every N blocks. Files are aligned to the multiples of N
+    #[clap(long, env, value_parser = clap::value_parser!(u64).range(1..))]
+    pub file_rotate_blocks: Option<u64>,
+    /// Start a new file when the current one reaches N bytes (checked between the blocks)
+    #[clap(long, env)]
+    pub file_rotate_bytes: Option<u64>,
+    /// Compress the files with gzip
+    #[clap(long, env)]
+    pub file_gzip: bool,
 }
 
 impl Opts {
@@ -0,0 +1,262 @@
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_lake_framework::near_indexer_primitives::types::AccountId;
+use sqlx::Row;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BalancePoint {
+    BlockHeight(u64),
+    /// Nanoseconds
+    Timestamp(u64),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BalanceSource {
+    /// Taken from `near_balance_events`
+    Indexed,
+    /// The account has no indexed history, asked the archival node
+    Rpc,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, PartialEq)]
+pub(crate) struct BalanceAt {
+    pub account_id: String,
+    /// For `indexed`, the block of the latest event. For `rpc`, the block we asked the node about
+    pub block_height: u64,
+    pub nonstaked_amount: String,
+    pub staked_amount: String,
+    pub source: BalanceSource,
+    /// The event the balance is taken from, for `indexed` only
+    pub event_index: Option<String>,
+}
+
+/// Balance of the account at the end of the block, or at the given moment.
+/// None if we can't tell it: there is no such block, or the timestamp is not covered by the index
+pub(crate) async fn balance_at(
+    pool: &sqlx::PgPool,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    account_id: &AccountId,
+    point: BalancePoint,
+) -> anyhow::Result<Option<BalanceAt>> {
+    let (column, value) = match point {
+        BalancePoint::BlockHeight(height) => ("block_height", height),
+        BalancePoint::Timestamp(timestamp) => ("block_timestamp", timestamp),
+    };
+    // The account may have changed after the latest indexed block, the index can't tell the balance there
+    let beyond_index = match latest_indexed_block(pool).await? {
+        Some((height, timestamp)) => match point {
+            BalancePoint::BlockHeight(_) => value > height,
+            BalancePoint::Timestamp(_) => value > timestamp,
+        },
+        None => true,
+    };
+    if beyond_index {
+        return match point {
+            BalancePoint::BlockHeight(height) => {
+                balance_from_rpc(json_rpc_client, account_id, height).await
+            }
+            // Only the index maps the timestamps to the blocks
+            BalancePoint::Timestamp(_) => Ok(None),
+        };
+    }
+
+    let latest_event = sqlx::query(&format!(
+        "SELECT CAST(block_height AS TEXT), CAST(event_index AS TEXT),
+            CAST(absolute_nonstaked_amount AS TEXT), CAST(absolute_staked_amount AS TEXT)
+        FROM near_balance_events
+        WHERE affected_account_id = $1 AND {} <= $2::numeric
+        ORDER BY event_index DESC
+        LIMIT 1",
+        column
+    ))
+    .bind(account_id.as_ref())
+    .bind(value.to_string())
+    .fetch_optional(pool)
+    .await?;
+    if let Some(row) = latest_event {
+        return Ok(Some(BalanceAt {
+            account_id: account_id.to_string(),
+            block_height: row.get::<String, _>(0).parse()?,
+            nonstaked_amount: row.get(2),
+            staked_amount: row.get(3),
+            source: BalanceSource::Indexed,
+            event_index: Some(row.get(1)),
+        }));
+    }
+
+    let block_height = match point {
+        BalancePoint::BlockHeight(height) => height,
+        // The account has no events up to the timestamp, and all the changes up to it are indexed,
+        // so its balance at the end of the latest indexed block before the timestamp is the answer
+        BalancePoint::Timestamp(timestamp) => {
+            // `block_timestamp` has no index, the primary key finds the block by `event_index`
+            let latest_block = sqlx::query(&format!(
+                "SELECT CAST(block_height AS TEXT) FROM near_balance_events
+                WHERE event_index < ($1::numeric + 1) * {}
+                ORDER BY event_index DESC
+                LIMIT 1",
+                crate::db_adapters::balance_buckets::EVENT_INDEX_TIMESTAMP_MULTIPLIER
+            ))
+            .bind(timestamp.to_string())
+            .fetch_optional(pool)
+            .await?;
+            match latest_block {
+                Some(row) => row.get::<String, _>(0).parse()?,
+                None => return Ok(None),
+            }
+        }
+    };
+    balance_from_rpc(json_rpc_client, account_id, block_height).await
+}
+
+/// Height and timestamp of the latest block with the events
+async fn latest_indexed_block(pool: &sqlx::PgPool) -> anyhow::Result<Option<(u64, u64)>> {
+    let latest_event = sqlx::query(
+        "SELECT CAST(block_height AS TEXT), CAST(block_timestamp AS TEXT)
+        FROM near_balance_events
+        ORDER BY event_index DESC
+        LIMIT 1",
+    )
+    .fetch_optional(pool)
+    .await?;
+    match latest_event {
+        Some(row) => Ok(Some((
+            row.get::<String, _>(0).parse()?,
+            row.get::<String, _>(1).parse()?,
+        ))),
+        None => Ok(None),
+    }
+}
+
+async fn balance_from_rpc(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    account_id: &AccountId,
+    block_height: u64,
+) -> anyhow::Result<Option<BalanceAt>> {
+    let (nonstaked_amount, staked_amount) =
+        match crate::db_adapters::balance_changes::get_account_view(
+            json_rpc_client,
+            account_id,
+            near_primitives::types::BlockId::Height(block_height),
+        )
+        .await
+        {
+            Ok(account_view) => (account_view.amount, account_view.locked),
+            Err(err) => match err.handler_error() {
+                Some(RpcQueryError::UnknownAccount { .. }) => (0, 0),
+                Some(RpcQueryError::UnknownBlock { .. }) => return Ok(None),
+                _ => return Err(err.into()),
+            },
+        };
+    Ok(Some(BalanceAt {
+        account_id: account_id.to_string(),
+        block_height,
+        nonstaked_amount: nonstaked_amount.to_string(),
+        staked_amount: staked_amount.to_string(),
+        source: BalanceSource::Rpc,
+        event_index: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_utils::{balance_event, block_header, start_mock_rpc, test_pool, RpcAccount};
+
+    #[tokio::test]
+    #[ignore]
+    async fn prefers_indexed_history() {
+        let (pool, _schema) = test_pool().await;
+        let json_rpc_client = start_mock_rpc(
+            near_primitives::types::BlockId::Height(3),
+            HashMap::from([(
+                "rpc-only.near".to_string(),
+                RpcAccount {
+                    amount: "7".to_string(),
+                    locked: "3".to_string(),
+                },
+            )]),
+        );
+        let first = block_header(1, 1_000_000_000);
+        let second = block_header(3, 3_000_000_000);
+        let events = vec![
+            balance_event(&first, 0, "alice.near", 10, 10),
+            balance_event(&second, 0, "alice.near", -4, 6),
+        ];
+        crate::models::chunked_insert(&pool, &events, crate::RETRY_COUNT)
+            .await
+            .unwrap();
+
+        let account_id: AccountId = "alice.near".parse().unwrap();
+        let at_height = balance_at(
+            &pool,
+            &json_rpc_client,
+            &account_id,
+            BalancePoint::BlockHeight(2),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let at_timestamp = balance_at(
+            &pool,
+            &json_rpc_client,
+            &account_id,
+            BalancePoint::Timestamp(2_500_000_000),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let from_rpc = balance_at(
+            &pool,
+            &json_rpc_client,
+            &"rpc-only.near".parse().unwrap(),
+            BalancePoint::Timestamp(3_000_000_000),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        // The account may have changed after the latest indexed block
+        let after_index_timestamp = balance_at(
+            &pool,
+            &json_rpc_client,
+            &account_id,
+            BalancePoint::Timestamp(u64::MAX),
+        )
+        .await
+        .unwrap();
+        let after_index_height = balance_at(
+            &pool,
+            &json_rpc_client,
+            &account_id,
+            BalancePoint::BlockHeight(u64::MAX),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            at_height,
+            BalanceAt {
+                account_id: "alice.near".to_string(),
+                block_height: 1,
+                nonstaked_amount: "10".to_string(),
+                staked_amount: "0".to_string(),
+                source: BalanceSource::Indexed,
+                event_index: Some(events[0].event_index.to_string()),
+            }
+        );
+        assert_eq!(at_timestamp.nonstaked_amount, "10");
+        assert_eq!(
+            (
+                from_rpc.source,
+                from_rpc.block_height,
+                from_rpc.staked_amount
+            ),
+            (BalanceSource::Rpc, 3, "3".to_string())
+        );
+        assert_eq!(after_index_timestamp, None);
+        assert_eq!(after_index_height.source, BalanceSource::Rpc);
+    }
+}
@@ -0,0 +1,215 @@
+use chrono::NaiveDate;
+
+use sqlx::Row;
+
+use crate::sinks::parquet::{day_start_nanosec, utc_day};
+
+/// `event_index` starts with the block timestamp in nanoseconds, followed by 16 digits
+pub(crate) const EVENT_INDEX_TIMESTAMP_MULTIPLIER: &str = "10000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Bucket {
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    const ALL: [Bucket; 2] = [Bucket::Hour, Bucket::Day];
+
+    /// Also the unit of `date_trunc`
+    fn name(&self) -> &'static str {
+        match self {
+            Bucket::Hour => "hour",
+            Bucket::Day => "day",
+        }
+    }
+}
+
+// The events are aggregated by the DB, so the incremental updates and the rebuild always agree.
+// Open is the balance before the first event of the bucket, close is the balance after the last one.
+// The balance before every event is the balance after the previous one, so min and max over both cover
+// all the values the account had in the bucket.
+// The update is skipped if the bucket already has these events, the replayed blocks are not counted twice
+fn upsert_query(bucket: Bucket, condition: &str) -> String {
+    format!(
+        "INSERT INTO account_balance_buckets (account_id, bucket, bucket_start,
+            open_amount, close_amount, min_amount, max_amount, inbound_amount, outbound_amount,
+            first_event_index, last_event_index)
+        SELECT affected_account_id,
+            '{bucket}',
+            date_trunc('{bucket}', to_timestamp(block_timestamp / 1000000000) AT TIME ZONE 'UTC') AS bucket_start,
+            (array_agg(absolute_nonstaked_amount - delta_nonstaked_amount ORDER BY event_index))[1],
+            (array_agg(absolute_nonstaked_amount ORDER BY event_index DESC))[1],
+            LEAST(min(absolute_nonstaked_amount), min(absolute_nonstaked_amount - delta_nonstaked_amount)),
+            GREATEST(max(absolute_nonstaked_amount), max(absolute_nonstaked_amount - delta_nonstaked_amount)),
+            COALESCE(sum(delta_nonstaked_amount) FILTER (WHERE delta_nonstaked_amount > 0), 0),
+            COALESCE(-sum(delta_nonstaked_amount) FILTER (WHERE delta_nonstaked_amount < 0), 0),
+            min(event_index),
+            max(event_index)
+        FROM near_balance_events
+        WHERE {condition}
+        GROUP BY affected_account_id, bucket_start
+        ON CONFLICT (account_id, bucket, bucket_start) DO UPDATE SET
+            close_amount = EXCLUDED.close_amount,
+            min_amount = LEAST(account_balance_buckets.min_amount, EXCLUDED.min_amount),
+            max_amount = GREATEST(account_balance_buckets.max_amount, EXCLUDED.max_amount),
+            inbound_amount = account_balance_buckets.inbound_amount + EXCLUDED.inbound_amount,
+            outbound_amount = account_balance_buckets.outbound_amount + EXCLUDED.outbound_amount,
+            last_event_index = EXCLUDED.last_event_index
+        WHERE account_balance_buckets.last_event_index < EXCLUDED.first_event_index",
+        bucket = bucket.name(),
+        condition = condition,
+    )
+}
+
+/// Adds the events of the block to the buckets. Called in the transaction which has stored the events
+pub(crate) async fn update_buckets(
+    connection: &mut sqlx::PgConnection,
+    block_height: u64,
+) -> anyhow::Result<()> {
+    for bucket in Bucket::ALL {
+        sqlx::query(&upsert_query(bucket, "block_height = $1::numeric"))
+            .bind(block_height.to_string())
+            .execute(&mut *connection)
+            .await?;
+    }
+    Ok(())
+}
+
+/// UTC days of the blocks stored in `near_balance_block_stats` in the range, inclusive
+pub(crate) async fn days_of_blocks(
+    pool: &sqlx::PgPool,
+    from_block: u64,
+    to_block: u64,
+) -> anyhow::Result<Vec<NaiveDate>> {
+    let timestamps = sqlx::query(
+        "SELECT CAST(min(block_timestamp) AS TEXT), CAST(max(block_timestamp) AS TEXT)
+        FROM near_balance_block_stats
+        WHERE block_height BETWEEN $1::numeric AND $2::numeric",
+    )
+    .bind(from_block.to_string())
+    .bind(to_block.to_string())
+    .fetch_one(pool)
+    .await?;
+    match (
+        timestamps.get::<Option<String>, _>(0),
+        timestamps.get::<Option<String>, _>(1),
+    ) {
+        (Some(first), Some(last)) => {
+            let last_day = utc_day(last.parse()?);
+            Ok(utc_day(first.parse()?)
+                .iter_days()
+                .take_while(|day| day <= &last_day)
+                .collect())
+        }
+        _ => Ok(vec![]),
+    }
+}
+
+/// Recomputes the buckets of the UTC day from `near_balance_events`, in one transaction
+pub(crate) async fn rebuild_day(pool: &sqlx::PgPool, day: NaiveDate) -> anyhow::Result<()> {
+    let next_day = day
+        .succ_opt()
+        .ok_or_else(|| anyhow::anyhow!("Invalid date {}", day))?;
+    let mut transaction = pool.begin().await?;
+    // The running indexer waits, otherwise its update may land between the delete and the insert
+    sqlx::query("LOCK TABLE account_balance_buckets IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query(
+        "DELETE FROM account_balance_buckets
+        WHERE bucket_start >= $1::timestamp AND bucket_start < $2::timestamp",
+    )
+    .bind(day.to_string())
+    .bind(next_day.to_string())
+    .execute(&mut *transaction)
+    .await?;
+
+    // The primary key of the events finds the day by `event_index`
+    let condition = format!(
+        "event_index >= $1::numeric * {multiplier} AND event_index < $2::numeric * {multiplier}",
+        multiplier = EVENT_INDEX_TIMESTAMP_MULTIPLIER
+    );
+    for bucket in Bucket::ALL {
+        sqlx::query(&upsert_query(bucket, &condition))
+            .bind(day_start_nanosec(day).to_string())
+            .bind(day_start_nanosec(next_day).to_string())
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    const HOUR: u64 = 3_600_000_000_000;
+
+    async fn buckets(pool: &sqlx::PgPool, account_id: &str) -> Vec<(String, Vec<BigDecimal>)> {
+        sqlx::query(
+            "SELECT bucket, open_amount, close_amount, min_amount, max_amount, inbound_amount, outbound_amount
+             FROM account_balance_buckets WHERE account_id = $1 ORDER BY bucket, bucket_start",
+        )
+        .bind(account_id)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| (row.get(0), (1..7).map(|i| row.get(i)).collect()))
+        .collect()
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn updates_and_rebuilds_buckets() {
+        let (pool, _schema) = test_pool().await;
+        let account_id = "alice.near";
+        // On 1970-01-01
+        let first = block_header(1, HOUR / 2);
+        let second = block_header(2, HOUR * 3 / 2);
+        let events = [
+            vec![
+                balance_event(&first, 0, account_id, 10, 110),
+                balance_event(&first, 1, account_id, -30, 80),
+            ],
+            vec![balance_event(&second, 0, account_id, 5, 85)],
+        ];
+
+        for (height, block_events) in [(1, &events[0]), (2, &events[1]), (1, &events[0])] {
+            let mut transaction = pool.begin().await.unwrap();
+            crate::models::chunked_insert_with_connection::<sqlx::Postgres, _>(
+                &mut *transaction,
+                block_events,
+            )
+            .await
+            .unwrap();
+            update_buckets(&mut transaction, height).await.unwrap();
+            transaction.commit().await.unwrap();
+        }
+        let incremental = buckets(&pool, account_id).await;
+        sqlx::query("UPDATE account_balance_buckets SET close_amount = 0 WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        rebuild_day(&pool, NaiveDate::from_ymd_opt(1970, 1, 1).unwrap())
+            .await
+            .unwrap();
+        let rebuilt = buckets(&pool, account_id).await;
+
+        let amounts = |amounts: [i32; 6]| amounts.iter().map(|&a| a.into()).collect::<Vec<_>>();
+        // Open, close, min, max, inbound, outbound
+        let expected = vec![
+            ("day".to_string(), amounts([100, 85, 80, 110, 15, 30])),
+            ("hour".to_string(), amounts([100, 80, 80, 110, 10, 30])),
+            ("hour".to_string(), amounts([80, 85, 80, 85, 5, 0])),
+        ];
+        assert_eq!(incremental, expected);
+        assert_eq!(rebuilt, expected);
+    }
+}
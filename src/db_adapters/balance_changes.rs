@@ -2,7 +2,9 @@ use cached::Cached;
 use std::collections::HashMap;
 use std::ops::Sub;
 use std::str::FromStr;
+use std::sync::atomic::Ordering;
 
+use crate::configs::UnexpectedCausePolicy;
 use crate::models::balance_changes::NearBalanceEvent;
 use crate::models::PrintEnum;
 use bigdecimal::BigDecimal;
@@ -19,17 +21,50 @@ use num_traits::Zero;
 
 // https://nomicon.io/RuntimeSpec/ApplyingChunk#processing-order
 pub(crate) async fn store_balance_changes(
-    pool: &sqlx::Pool<sqlx::Postgres>,
+    sinks: &crate::sinks::Sinks,
     shards: &[near_indexer_primitives::IndexerShard],
     block_header: &near_indexer_primitives::views::BlockHeaderView,
     balances_cache: &crate::BalanceCache,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    unexpected_causes: &crate::configs::UnexpectedCausesConfig,
+    supply_checker: Option<&crate::db_adapters::supply_check::SupplyChecker>,
 ) -> anyhow::Result<()> {
+    let changes = collect_balance_changes(
+        shards,
+        block_header,
+        balances_cache,
+        json_rpc_client,
+        unexpected_causes,
+    )
+    .await?;
+    sinks.store_block(block_header, &changes).await?;
+    if let Some(supply_checker) = supply_checker {
+        supply_checker
+            .check(shards, block_header, &changes, json_rpc_client)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Extracts the balance-changing events for the whole block, ordered by `event_index`
+pub(crate) async fn collect_balance_changes(
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    balances_cache: &crate::BalanceCache,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    unexpected_causes: &crate::configs::UnexpectedCausesConfig,
+) -> anyhow::Result<Vec<NearBalanceEvent>> {
     let futures = shards.iter().map(|shard| {
-        store_changes_for_chunk(pool, shard, block_header, balances_cache, json_rpc_client)
+        collect_changes_for_chunk(
+            shard,
+            block_header,
+            balances_cache,
+            json_rpc_client,
+            unexpected_causes,
+        )
     });
 
-    try_join_all(futures).await.map(|_| ())
+    Ok(try_join_all(futures).await?.into_iter().flatten().collect())
 }
 
 #[derive(Debug, Default)]
@@ -38,19 +73,49 @@ struct AccountChangesBalances {
     pub transactions: HashMap<near_indexer_primitives::CryptoHash, crate::AccountWithBalance>,
     pub receipts: HashMap<near_indexer_primitives::CryptoHash, crate::AccountWithBalance>,
     pub rewards: HashMap<near_indexer_primitives::CryptoHash, crate::AccountWithBalance>,
+    pub unknown: Vec<UnknownCauseChange>,
+}
+
+/// Balance change with the cause we do not expect to change the balances, recorded by the policy
+#[derive(Debug)]
+struct UnknownCauseChange {
+    pub account: crate::AccountWithBalance,
+    pub receipt_id: Option<near_indexer_primitives::CryptoHash>,
+    /// The state change with its cause in JSON
+    pub payload: String,
+    /// The account has the changes with the known causes after this one in the same chunk
+    pub followed_by_known_change: bool,
 }
 
-async fn store_changes_for_chunk(
-    pool: &sqlx::Pool<sqlx::Postgres>,
+async fn collect_changes_for_chunk(
     shard: &near_indexer_primitives::IndexerShard,
     block_header: &near_indexer_primitives::views::BlockHeaderView,
     balances_cache: &crate::BalanceCache,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
-) -> anyhow::Result<()> {
+    unexpected_causes: &crate::configs::UnexpectedCausesConfig,
+) -> anyhow::Result<Vec<NearBalanceEvent>> {
     let mut changes: Vec<NearBalanceEvent> = vec![];
-    let mut changes_data =
-        collect_data_from_balance_changes(&shard.state_changes, block_header.height)?;
-    // We should collect these 3 groups sequentially because they all share the same cache
+    let mut changes_data = collect_data_from_balance_changes(
+        &shard.state_changes,
+        block_header.height,
+        unexpected_causes,
+    )?;
+    // We don't know where they are in the processing order.
+    // The ones followed by the known changes of the same account go first, so that these known changes
+    // count their deltas from them. The others go last, so that they do not overwrite the latest balance
+    let (unknown_first, unknown_last): (Vec<_>, Vec<_>) = std::mem::take(&mut changes_data.unknown)
+        .into_iter()
+        .partition(|change| change.followed_by_known_change);
+    // We should collect these groups sequentially because they all share the same cache
+    changes.extend(
+        store_unknown_cause_changes_for_chunk(
+            &unknown_first,
+            block_header,
+            balances_cache,
+            json_rpc_client,
+        )
+        .await?,
+    );
     changes.extend(
         store_validator_accounts_update_for_chunk(
             &changes_data.validators,
@@ -85,19 +150,28 @@ async fn store_changes_for_chunk(
         )
         .await?,
     );
+    changes.extend(
+        store_unknown_cause_changes_for_chunk(
+            &unknown_last,
+            block_header,
+            balances_cache,
+            json_rpc_client,
+        )
+        .await?,
+    );
 
     let start_from_index: u128 = (block_header.timestamp as u128) * 100_000_000 * 100_000_000
         + (shard.shard_id as u128) * 10_000_000;
     for (i, change) in changes.iter_mut().enumerate() {
         change.event_index = BigDecimal::from_str(&(start_from_index + i as u128).to_string())?;
     }
-    crate::models::chunked_insert(pool, &changes, 10).await?;
-    Ok(())
+    Ok(changes)
 }
 
 fn collect_data_from_balance_changes(
     state_changes: &near_indexer_primitives::views::StateChangesView,
     block_height: u64,
+    unexpected_causes: &crate::configs::UnexpectedCausesConfig,
 ) -> anyhow::Result<AccountChangesBalances> {
     let mut result: AccountChangesBalances = Default::default();
 
@@ -128,6 +202,13 @@ fn collect_data_from_balance_changes(
             // other values do not provide balance changes
             _ => continue,
         };
+        if is_known_cause(cause) {
+            for change in result.unknown.iter_mut() {
+                if change.account.account_id == account_details.account_id {
+                    change.followed_by_known_change = true;
+                }
+            }
+        }
 
         match cause {
             StateChangeCauseView::NotWritableToDisk
@@ -136,7 +217,44 @@ fn collect_data_from_balance_changes(
             | StateChangeCauseView::UpdatedDelayedReceipts
             | StateChangeCauseView::PostponedReceipt { .. }
             | StateChangeCauseView::Resharding => {
-                anyhow::bail!("Unexpected state change cause met: {:#?}", cause);
+                let cause_type = serde_json::to_value(cause)?["type"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+                match unexpected_causes.policy(&cause_type) {
+                    UnexpectedCausePolicy::Fail => {
+                        anyhow::bail!("Unexpected state change cause met: {:#?}", cause);
+                    }
+                    UnexpectedCausePolicy::Skip => {
+                        crate::metrics::SKIPPED_CAUSES_TOTAL
+                            .with_label_values(&[&cause_type])
+                            .inc();
+                        tracing::warn!(
+                            target: crate::LOGGING_PREFIX,
+                            "Skipping balance change of {} at block_height {} with unexpected cause {:?}",
+                            account_details.account_id,
+                            block_height,
+                            cause,
+                        );
+                    }
+                    UnexpectedCausePolicy::Record => {
+                        let receipt_id = match cause {
+                            StateChangeCauseView::ActionReceiptProcessingStarted {
+                                receipt_hash,
+                            }
+                            | StateChangeCauseView::PostponedReceipt { receipt_hash } => {
+                                Some(*receipt_hash)
+                            }
+                            _ => None,
+                        };
+                        result.unknown.push(UnknownCauseChange {
+                            account: account_details,
+                            receipt_id,
+                            payload: serde_json::to_string(state_change_with_cause)?,
+                            followed_by_known_change: false,
+                        });
+                    }
+                }
             }
             StateChangeCauseView::ValidatorAccountsUpdate => {
                 result.validators.push(account_details);
@@ -156,7 +274,7 @@ fn collect_data_from_balance_changes(
                     );
                 }
             }
-            StateChangeCauseView::Migration {} => {
+            StateChangeCauseView::Migration => {
                 // We had this reason once, in block 44337060
                 // It does not affect balances, so we can skip it
             }
@@ -195,6 +313,16 @@ fn collect_data_from_balance_changes(
     Ok(result)
 }
 
+fn is_known_cause(cause: &StateChangeCauseView) -> bool {
+    matches!(
+        cause,
+        StateChangeCauseView::ValidatorAccountsUpdate
+            | StateChangeCauseView::TransactionProcessing { .. }
+            | StateChangeCauseView::ActionReceiptGasReward { .. }
+            | StateChangeCauseView::ReceiptProcessing { .. }
+    )
+}
+
 async fn store_validator_accounts_update_for_chunk(
     validator_changes: &[crate::AccountWithBalance],
     block_header: &near_indexer_primitives::views::BlockHeaderView,
@@ -205,7 +333,7 @@ async fn store_validator_accounts_update_for_chunk(
     for new_details in validator_changes {
         let prev_balance = get_balance_retriable(
             &new_details.account_id,
-            &block_header.prev_hash,
+            block_header,
             balances_cache,
             json_rpc_client,
         )
@@ -214,6 +342,7 @@ async fn store_validator_accounts_update_for_chunk(
         save_latest_balance(
             new_details.account_id.clone(),
             &new_details.balance,
+            block_header,
             balances_cache,
         )
         .await;
@@ -239,6 +368,66 @@ async fn store_validator_accounts_update_for_chunk(
             delta_staked_amount: deltas.1,
             absolute_staked_amount: BigDecimal::from_str(&new_details.balance.staked.to_string())
                 .unwrap(),
+            cause_payload: None,
+        });
+    }
+
+    Ok(result)
+}
+
+async fn store_unknown_cause_changes_for_chunk(
+    unknown_changes: &[UnknownCauseChange],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    balances_cache: &crate::BalanceCache,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<Vec<NearBalanceEvent>> {
+    let mut result: Vec<NearBalanceEvent> = vec![];
+    for change in unknown_changes {
+        let new_details = &change.account;
+        let prev_balance = get_balance_retriable(
+            &new_details.account_id,
+            block_header,
+            balances_cache,
+            json_rpc_client,
+        )
+        .await?;
+        let deltas = get_deltas(&new_details.balance, &prev_balance)?;
+        save_latest_balance(
+            new_details.account_id.clone(),
+            &new_details.balance,
+            block_header,
+            balances_cache,
+        )
+        .await;
+
+        result.push(NearBalanceEvent {
+            event_index: BigDecimal::zero(), // will enumerate later
+            block_timestamp: block_header.timestamp.into(),
+            block_height: block_header.height.into(),
+            receipt_id: change.receipt_id.map(|receipt_id| receipt_id.to_string()),
+            transaction_hash: None,
+            affected_account_id: new_details.account_id.to_string(),
+            involved_account_id: None,
+            direction: if deltas.0 < BigDecimal::zero() {
+                crate::models::Direction::Outbound
+            } else {
+                crate::models::Direction::Inbound
+            }
+            .print()
+            .to_string(),
+            cause: crate::models::Cause::Unknown.print().to_string(),
+            status: ExecutionStatusView::SuccessValue("".to_string())
+                .print()
+                .to_string(),
+            delta_nonstaked_amount: deltas.0,
+            absolute_nonstaked_amount: BigDecimal::from_str(
+                &new_details.balance.non_staked.to_string(),
+            )
+            .unwrap(),
+            delta_staked_amount: deltas.1,
+            absolute_staked_amount: BigDecimal::from_str(&new_details.balance.staked.to_string())
+                .unwrap(),
+            cause_payload: Some(change.payload.clone()),
         });
     }
 
@@ -266,7 +455,7 @@ async fn store_transaction_execution_outcomes_for_chunk(
 
         let prev_balance = get_balance_retriable(
             affected_account_id,
-            &block_header.prev_hash,
+            block_header,
             balances_cache,
             json_rpc_client,
         )
@@ -294,6 +483,7 @@ async fn store_transaction_execution_outcomes_for_chunk(
         save_latest_balance(
             affected_account_id.clone(),
             &details_after_transaction.balance,
+            block_header,
             balances_cache,
         )
         .await;
@@ -325,6 +515,7 @@ async fn store_transaction_execution_outcomes_for_chunk(
                 &details_after_transaction.balance.staked.to_string(),
             )
             .unwrap(),
+            cause_payload: None,
         });
 
         // Adding the opposite entry to the DB, just to show that the second account_id was there too
@@ -333,7 +524,7 @@ async fn store_transaction_execution_outcomes_for_chunk(
                 // balance is not changing here, we just note the line here
                 let balance = get_balance_retriable(
                     account_id,
-                    &block_header.prev_hash,
+                    block_header,
                     balances_cache,
                     json_rpc_client,
                 )
@@ -363,6 +554,7 @@ async fn store_transaction_execution_outcomes_for_chunk(
                     delta_staked_amount: BigDecimal::zero(),
                     absolute_staked_amount: BigDecimal::from_str(&balance.staked.to_string())
                         .unwrap(),
+                    cause_payload: None,
                 });
             }
         }
@@ -411,7 +603,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
 
             let prev_balance = get_balance_retriable(
                 affected_account_id,
-                &block_header.prev_hash,
+                block_header,
                 balances_cache,
                 json_rpc_client,
             )
@@ -421,6 +613,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
             save_latest_balance(
                 affected_account_id.clone(),
                 &details_after_receipt.balance,
+                block_header,
                 balances_cache,
             )
             .await;
@@ -451,6 +644,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
                     &details_after_receipt.balance.staked.to_string(),
                 )
                 .unwrap(),
+                cause_payload: None,
             });
 
             // Adding the opposite entry to the DB, just to show that the second account_id was there too
@@ -459,7 +653,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
                     // balance is not changing here, we just note the line here
                     let balance = get_balance_retriable(
                         account_id,
-                        &block_header.prev_hash,
+                        block_header,
                         balances_cache,
                         json_rpc_client,
                     )
@@ -488,6 +682,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
                         delta_staked_amount: BigDecimal::zero(),
                         absolute_staked_amount: BigDecimal::from_str(&balance.staked.to_string())
                             .unwrap(),
+                        cause_payload: None,
                     });
                 }
             }
@@ -506,7 +701,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
 
             let prev_balance = get_balance_retriable(
                 affected_account_id,
-                &block_header.prev_hash,
+                block_header,
                 balances_cache,
                 json_rpc_client,
             )
@@ -515,6 +710,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
             save_latest_balance(
                 affected_account_id.clone(),
                 &details_after_reward.balance,
+                block_header,
                 balances_cache,
             )
             .await;
@@ -545,6 +741,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
                     &details_after_reward.balance.staked.to_string(),
                 )
                 .unwrap(),
+                cause_payload: None,
             });
         }
     }
@@ -581,9 +778,10 @@ fn get_deltas(
     ))
 }
 
-async fn get_balance_retriable(
+/// Balance before the block, cached
+pub(crate) async fn get_balance_retriable(
     account_id: &near_indexer_primitives::types::AccountId,
-    block_hash: &near_indexer_primitives::CryptoHash,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
     balance_cache: &crate::BalanceCache,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
 ) -> anyhow::Result<crate::BalanceDetails> {
@@ -596,19 +794,19 @@ async fn get_balance_retriable(
                 "Failed to perform query to RPC after {} attempts. Stop trying.\nAccount {}, block_hash {}",
                 crate::RETRY_COUNT,
                 account_id.to_string(),
-                block_hash.to_string()
+                block_header.prev_hash.to_string()
             );
         }
         retry_attempt += 1;
 
-        match get_balance(account_id, block_hash, balance_cache, json_rpc_client).await {
+        match get_balance(account_id, block_header, balance_cache, json_rpc_client).await {
             Ok(res) => return Ok(res),
             Err(err) => {
                 tracing::error!(
                     target: crate::LOGGING_PREFIX,
                     "Failed to request account view details from RPC for account {}, block_hash {}.{}\n Retrying in {} milliseconds...",
                     account_id.to_string(),
-                    block_hash.to_string(),
+                    block_header.prev_hash.to_string(),
                     err,
                     interval.as_millis(),
                 );
@@ -623,63 +821,122 @@ async fn get_balance_retriable(
 
 async fn get_balance(
     account_id: &near_indexer_primitives::types::AccountId,
-    block_hash: &near_indexer_primitives::CryptoHash,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
     balance_cache: &crate::BalanceCache,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
 ) -> anyhow::Result<crate::BalanceDetails> {
-    let mut balances_cache_lock = balance_cache.lock().await;
-    let result = match balances_cache_lock.cache_get(account_id) {
+    let mut balances_cache_lock = balance_cache.balances.lock().await;
+    let cached = match balances_cache_lock.cache_get(account_id).copied() {
         None => {
             let account_balance =
-                match get_account_view(json_rpc_client, account_id, block_hash).await {
-                    Ok(account_view) => Ok(crate::BalanceDetails {
-                        non_staked: account_view.amount,
-                        staked: account_view.locked,
-                    }),
-                    Err(err) => match err.handler_error() {
-                        Some(RpcQueryError::UnknownAccount { .. }) => Ok(crate::BalanceDetails {
-                            non_staked: 0,
-                            staked: 0,
-                        }),
-                        _ => Err(err.into()),
-                    },
-                };
+                get_balance_from_rpc(account_id, &block_header.prev_hash, json_rpc_client).await;
             if let Ok(balance) = account_balance {
-                balances_cache_lock.cache_set(account_id.clone(), balance);
+                balances_cache_lock.cache_set(
+                    account_id.clone(),
+                    crate::CachedBalance {
+                        balance,
+                        block_hash: block_header.hash,
+                    },
+                );
             }
-            account_balance
+            return account_balance;
         }
-        Some(balance) => Ok(*balance),
+        Some(cached) => cached,
     };
     drop(balances_cache_lock);
-    result
+
+    // The balance changed earlier in this block is not the one at the end of the previous block
+    if cached.block_hash == block_header.hash {
+        return Ok(cached.balance);
+    }
+    let hit = balance_cache.hits.fetch_add(1, Ordering::Relaxed);
+    if !is_sampled(hit, balance_cache.check_percent) {
+        return Ok(cached.balance);
+    }
+    crate::metrics::CACHE_CHECKS_TOTAL.inc();
+    let rpc_balance =
+        get_balance_from_rpc(account_id, &block_header.prev_hash, json_rpc_client).await?;
+    let balance = cached.balance;
+    if (rpc_balance.non_staked, rpc_balance.staked) == (balance.non_staked, balance.staked) {
+        return Ok(balance);
+    }
+    crate::metrics::CACHE_MISMATCHES_TOTAL.inc();
+    tracing::warn!(
+        target: crate::LOGGING_PREFIX,
+        "Cached balance of {} differs from RPC at block_hash {}: cached {:?}, RPC {:?}. Evicting it",
+        account_id,
+        block_header.prev_hash,
+        balance,
+        rpc_balance,
+    );
+    let mut balances_cache_lock = balance_cache.balances.lock().await;
+    // Evict it only if nobody has updated it while we were asking RPC
+    if balances_cache_lock
+        .cache_get(account_id)
+        .map_or(false, |current| current.block_hash == cached.block_hash)
+    {
+        balances_cache_lock.cache_remove(account_id);
+    }
+    drop(balances_cache_lock);
+    Ok(rpc_balance)
+}
+
+/// Picks `percent` of the cache hits, spread evenly
+fn is_sampled(hit: u64, percent: u8) -> bool {
+    let percent = u64::from(percent);
+    (hit + 1) * percent / 100 > hit * percent / 100
+}
+
+async fn get_balance_from_rpc(
+    account_id: &near_indexer_primitives::types::AccountId,
+    block_hash: &near_indexer_primitives::CryptoHash,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<crate::BalanceDetails> {
+    match get_account_view(
+        json_rpc_client,
+        account_id,
+        near_primitives::types::BlockId::Hash(*block_hash),
+    )
+    .await
+    {
+        Ok(account_view) => Ok(crate::BalanceDetails {
+            non_staked: account_view.amount,
+            staked: account_view.locked,
+        }),
+        Err(err) => match err.handler_error() {
+            Some(RpcQueryError::UnknownAccount { .. }) => Ok(crate::BalanceDetails {
+                non_staked: 0,
+                staked: 0,
+            }),
+            _ => Err(err.into()),
+        },
+    }
 }
 
 async fn save_latest_balance(
     account_id: near_indexer_primitives::types::AccountId,
     balance: &crate::BalanceDetails,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
     balance_cache: &crate::BalanceCache,
 ) {
-    let mut balances_cache_lock = balance_cache.lock().await;
+    let mut balances_cache_lock = balance_cache.balances.lock().await;
     balances_cache_lock.cache_set(
         account_id,
-        crate::BalanceDetails {
-            non_staked: balance.non_staked,
-            staked: balance.staked,
+        crate::CachedBalance {
+            balance: *balance,
+            block_hash: block_header.hash,
         },
     );
     drop(balances_cache_lock);
 }
 
-async fn get_account_view(
+pub(crate) async fn get_account_view(
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
     account_id: &near_indexer_primitives::types::AccountId,
-    block_hash: &near_indexer_primitives::CryptoHash,
+    block_id: near_primitives::types::BlockId,
 ) -> Result<near_indexer_primitives::views::AccountView, JsonRpcError<RpcQueryError>> {
     let query = near_jsonrpc_client::methods::query::RpcQueryRequest {
-        block_reference: near_primitives::types::BlockReference::BlockId(
-            near_primitives::types::BlockId::Hash(*block_hash),
-        ),
+        block_reference: near_primitives::types::BlockReference::BlockId(block_id.clone()),
         request: near_primitives::views::QueryRequest::ViewAccount {
             account_id: account_id.clone(),
         },
@@ -691,11 +948,14 @@ async fn get_account_view(
             Ok(account)
         }
         _ => unreachable!(
-            "Unreachable code! Asked for ViewAccount (block_hash {}, account_id {})\nReceived\n\
+            "Unreachable code! Asked for ViewAccount (block_id {:?}, account_id {})\nReceived\n\
                 {:#?}\nReport this to https://github.com/near/near-jsonrpc-client-rs",
-            block_hash.to_string(),
+            block_id,
             account_id.to_string(),
             account_response.kind
         ),
     }
 }
+
+#[cfg(test)]
+mod tests;
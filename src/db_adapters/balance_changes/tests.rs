@@ -0,0 +1,317 @@
+//! Tests over hand-written blocks, one per case of the merge logic.
+//!
+//! Every fixture in `tests/fixtures/balance_changes` contains a synthetic `StreamerMessage`
+//! (the accounts, hashes and balances are made up), the balances the mock RPC returns
+//! at its previous block, and the exact list of events (including their order) we expect to produce.
+use std::collections::HashMap;
+
+use cached::Cached;
+use near_lake_framework::near_indexer_primitives::{CryptoHash, StreamerMessage};
+use near_primitives::types::BlockId;
+
+use crate::configs::UnexpectedCausePolicy;
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::test_utils::{block_header, start_mock_rpc, RpcAccount};
+
+#[derive(Debug, serde::Deserialize)]
+struct Fixture {
+    rpc_accounts: HashMap<String, RpcAccount>,
+    streamer_message: StreamerMessage,
+    expected_events: Vec<NearBalanceEvent>,
+}
+
+fn load_fixture(name: &str) -> Fixture {
+    let path = format!(
+        "{}/tests/fixtures/balance_changes/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    let content = std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Failed to read fixture {}: {}", path, err));
+    serde_json::from_str(&content)
+        .unwrap_or_else(|err| panic!("Failed to parse fixture {}: {}", path, err))
+}
+
+async fn assert_fixture(name: &str) {
+    assert_fixture_with_causes(name, Default::default()).await;
+}
+
+async fn assert_fixture_with_causes(
+    name: &str,
+    unexpected_causes: crate::configs::UnexpectedCausesConfig,
+) {
+    let fixture = load_fixture(name);
+    let json_rpc_client = start_mock_rpc(
+        BlockId::Hash(fixture.streamer_message.block.header.prev_hash),
+        fixture.rpc_accounts,
+    );
+    let balances_cache = crate::BalanceCache::new(100, 0);
+
+    let events = super::collect_balance_changes(
+        &fixture.streamer_message.shards,
+        &fixture.streamer_message.block.header,
+        &balances_cache,
+        &json_rpc_client,
+        &unexpected_causes,
+    )
+    .await
+    .expect("Failed to collect balance changes");
+
+    assert_eq!(
+        events.len(),
+        fixture.expected_events.len(),
+        "Unexpected number of events for {}:\n{:#?}",
+        name,
+        events
+    );
+    for (actual, expected) in events.iter().zip(fixture.expected_events.iter()) {
+        // `cause_payload` is not serialized, so the fixtures can't have it
+        let actual = NearBalanceEvent {
+            cause_payload: None,
+            ..actual.clone()
+        };
+        assert_eq!(&actual, expected, "Unexpected event for {}", name);
+    }
+}
+
+#[tokio::test]
+async fn validators_reward() {
+    assert_fixture("validators_reward").await;
+}
+
+#[tokio::test]
+async fn failed_transaction() {
+    assert_fixture("failed_transaction").await;
+}
+
+#[tokio::test]
+async fn account_deletion() {
+    assert_fixture("account_deletion").await;
+}
+
+#[tokio::test]
+async fn gas_reward() {
+    assert_fixture("gas_reward").await;
+}
+
+#[tokio::test]
+async fn migration_cause() {
+    assert_fixture("migration_cause").await;
+}
+
+#[tokio::test]
+async fn unexpected_cause_stops_indexing() {
+    let fixture = load_fixture("postponed_receipt");
+    let json_rpc_client = start_mock_rpc(
+        BlockId::Hash(fixture.streamer_message.block.header.prev_hash),
+        fixture.rpc_accounts,
+    );
+    let balances_cache = crate::BalanceCache::new(100, 0);
+
+    let result = super::collect_balance_changes(
+        &fixture.streamer_message.shards,
+        &fixture.streamer_message.block.header,
+        &balances_cache,
+        &json_rpc_client,
+        &Default::default(),
+    )
+    .await;
+    let err = result.expect_err("PostponedReceipt must not be silently ignored");
+    assert!(err
+        .to_string()
+        .starts_with("Unexpected state change cause met"));
+}
+
+#[test]
+fn samples_cache_hits_evenly() {
+    let sampled = |percent| {
+        (0..100)
+            .filter(|hit| super::is_sampled(*hit, percent))
+            .count()
+    };
+    assert_eq!(sampled(0), 0);
+    assert_eq!(sampled(25), 25);
+    assert_eq!(sampled(100), 100);
+    // One of every four hits
+    assert_eq!((0..4).filter(|hit| super::is_sampled(*hit, 25)).count(), 1);
+}
+
+fn alice_with_rpc_balance(
+    non_staked: u128,
+    block_hash: CryptoHash,
+) -> (
+    near_lake_framework::near_indexer_primitives::types::AccountId,
+    near_jsonrpc_client::JsonRpcClient,
+) {
+    let json_rpc_client = start_mock_rpc(
+        BlockId::Hash(block_hash),
+        HashMap::from([(
+            "alice.near".to_string(),
+            RpcAccount {
+                amount: non_staked.to_string(),
+                locked: "0".to_string(),
+            },
+        )]),
+    );
+    ("alice.near".parse().unwrap(), json_rpc_client)
+}
+
+#[tokio::test]
+async fn evicts_diverged_cached_balance() {
+    let balances_cache = crate::BalanceCache::new(100, 100);
+    let previous_block = block_header(70_000_000, 1_657_000_000_000_000_000);
+    let mut block = block_header(70_000_001, 1_657_000_001_000_000_000);
+    block.prev_hash = previous_block.hash;
+    block.hash = CryptoHash::hash_bytes(b"70000001");
+    let (account_id, json_rpc_client) = alice_with_rpc_balance(10, block.prev_hash);
+    // The balance saved after the wrong delta
+    super::save_latest_balance(
+        account_id.clone(),
+        &crate::BalanceDetails {
+            non_staked: 7,
+            staked: 0,
+        },
+        &previous_block,
+        &balances_cache,
+    )
+    .await;
+
+    let mismatches = crate::metrics::CACHE_MISMATCHES_TOTAL.get();
+    let balance = super::get_balance(&account_id, &block, &balances_cache, &json_rpc_client)
+        .await
+        .unwrap();
+    assert_eq!(balance.non_staked, 10);
+    assert_eq!(crate::metrics::CACHE_MISMATCHES_TOTAL.get(), mismatches + 1);
+    assert!(balances_cache
+        .balances
+        .lock()
+        .await
+        .cache_get(&account_id)
+        .is_none());
+}
+
+#[tokio::test]
+async fn does_not_check_balance_changed_in_the_same_block() {
+    let balances_cache = crate::BalanceCache::new(100, 100);
+    let block = block_header(70_000_000, 1_657_000_000_000_000_000);
+    let (account_id, json_rpc_client) = alice_with_rpc_balance(10, block.prev_hash);
+
+    // E.g. the transaction, and then its local receipt in the same chunk
+    let before_transaction =
+        super::get_balance(&account_id, &block, &balances_cache, &json_rpc_client)
+            .await
+            .unwrap();
+    assert_eq!(before_transaction.non_staked, 10);
+    super::save_latest_balance(
+        account_id.clone(),
+        &crate::BalanceDetails {
+            non_staked: 12,
+            staked: 0,
+        },
+        &block,
+        &balances_cache,
+    )
+    .await;
+    let before_receipt = super::get_balance(&account_id, &block, &balances_cache, &json_rpc_client)
+        .await
+        .unwrap();
+
+    // RPC still has the balance before the block, it's not the one to compare with
+    assert_eq!(before_receipt.non_staked, 12);
+    assert_eq!(
+        balances_cache
+            .hits
+            .load(std::sync::atomic::Ordering::Relaxed),
+        0
+    );
+    assert_eq!(
+        balances_cache
+            .balances
+            .lock()
+            .await
+            .cache_get(&account_id)
+            .map(|cached| cached.balance.non_staked),
+        Some(12)
+    );
+}
+
+async fn collect_postponed_receipt(
+    unexpected_causes: crate::configs::UnexpectedCausesConfig,
+) -> Vec<NearBalanceEvent> {
+    let fixture = load_fixture("postponed_receipt");
+    let json_rpc_client = start_mock_rpc(
+        BlockId::Hash(fixture.streamer_message.block.header.prev_hash),
+        fixture.rpc_accounts,
+    );
+    let balances_cache = crate::BalanceCache::new(100, 0);
+
+    super::collect_balance_changes(
+        &fixture.streamer_message.shards,
+        &fixture.streamer_message.block.header,
+        &balances_cache,
+        &json_rpc_client,
+        &unexpected_causes,
+    )
+    .await
+    .expect("Failed to collect balance changes")
+}
+
+#[tokio::test]
+async fn skips_unexpected_cause() {
+    let skipped = || {
+        crate::metrics::SKIPPED_CAUSES_TOTAL
+            .with_label_values(&["postponed_receipt"])
+            .get()
+    };
+    let skipped_before = skipped();
+    let events = collect_postponed_receipt(crate::configs::UnexpectedCausesConfig {
+        unexpected_cause_policy: UnexpectedCausePolicy::Record,
+        unexpected_cause_policies: vec![(
+            "postponed_receipt".to_string(),
+            UnexpectedCausePolicy::Skip,
+        )],
+    })
+    .await;
+    assert!(events.is_empty());
+    assert_eq!(skipped(), skipped_before + 1);
+}
+
+#[tokio::test]
+async fn records_unexpected_cause() {
+    let events = collect_postponed_receipt(crate::configs::UnexpectedCausesConfig {
+        unexpected_cause_policy: UnexpectedCausePolicy::Record,
+        unexpected_cause_policies: vec![],
+    })
+    .await;
+    assert_eq!(events.len(), 1);
+    let event = &events[0];
+    assert_eq!(
+        (event.cause.as_str(), event.direction.as_str()),
+        ("UNKNOWN", "INBOUND")
+    );
+    assert_eq!(
+        event.receipt_id.as_deref(),
+        Some("CsAvrUd9cBAmH2zaTzxPpx9uBwLYWnCfxWEyZ8aRTfzs")
+    );
+    // The account did not exist before the block
+    assert_eq!(
+        event.delta_nonstaked_amount,
+        event.absolute_nonstaked_amount
+    );
+    let payload: serde_json::Value =
+        serde_json::from_str(event.cause_payload.as_ref().unwrap()).unwrap();
+    assert_eq!(payload["cause"]["type"], "postponed_receipt");
+    assert_eq!(payload["change"]["account_id"], "alice.near");
+}
+
+#[tokio::test]
+async fn records_unexpected_cause_before_known_changes_of_the_account() {
+    assert_fixture_with_causes(
+        "mixed_cause_account",
+        crate::configs::UnexpectedCausesConfig {
+            unexpected_cause_policy: UnexpectedCausePolicy::Record,
+            unexpected_cause_policies: vec![],
+        },
+    )
+    .await;
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+use futures::TryStreamExt;
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use num_traits::ToPrimitive;
+
+use crate::models::account_balances_latest::AccountBalanceLatest;
+use crate::models::balance_changes::NearBalanceEvent;
+
+/// The event does not continue the previous event of the account:
+/// previous absolute amount + delta is not the absolute amount
+#[derive(serde::Serialize, sqlx::FromRow, Debug, Clone, PartialEq)]
+pub(crate) struct ContinuityBreak {
+    pub account_id: String,
+    pub previous_event_index: BigDecimal,
+    pub previous_block_height: BigDecimal,
+    pub previous_nonstaked_amount: BigDecimal,
+    pub previous_staked_amount: BigDecimal,
+    pub event_index: BigDecimal,
+    pub block_height: BigDecimal,
+    pub delta_nonstaked_amount: BigDecimal,
+    pub delta_staked_amount: BigDecimal,
+    pub absolute_nonstaked_amount: BigDecimal,
+    pub absolute_staked_amount: BigDecimal,
+    /// Block of the next event of the account, None if it's the latest one
+    #[serde(skip)]
+    #[sqlx(default)]
+    pub next_block_height: Option<BigDecimal>,
+}
+
+/// Which side of the break disagrees with the archival node
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum Verdict {
+    PreviousWrong,
+    CurrentWrong,
+    BothWrong,
+    /// Both rows are right, the changes between them are not indexed
+    MissingEvents,
+    /// The node does not know the blocks, or the rows are not the last ones of the account in their blocks,
+    /// so they can't be compared with the balance at the end of the block
+    Unknown,
+}
+
+impl Verdict {
+    /// Whether the previous and the current rows match the node, None if they can't be compared
+    fn from_matches(previous: Option<bool>, current: Option<bool>) -> Self {
+        match (previous, current) {
+            (Some(true), Some(true)) => Verdict::MissingEvents,
+            (Some(false), Some(false)) => Verdict::BothWrong,
+            (Some(false), _) => Verdict::PreviousWrong,
+            (_, Some(false)) => Verdict::CurrentWrong,
+            _ => Verdict::Unknown,
+        }
+    }
+}
+
+/// Filters of `verify` walk, all of them are optional
+#[derive(Debug, Clone, Default)]
+pub(crate) struct VerifyRange {
+    pub account_id: Option<String>,
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+}
+
+/// Walks `near_balance_events` of every account in `event_index` order and passes the breaks to `on_break`.
+/// The first event of the account in the range is not compared with the events before the range
+pub(crate) async fn find_breaks<F, Fut>(
+    pool: &sqlx::PgPool,
+    range: &VerifyRange,
+    mut on_break: F,
+) -> anyhow::Result<usize>
+where
+    F: FnMut(ContinuityBreak) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<()>>,
+{
+    let mut breaks = sqlx::query_as::<_, ContinuityBreak>(
+        "SELECT affected_account_id AS account_id,
+            previous_event_index, previous_block_height, previous_nonstaked_amount, previous_staked_amount,
+            event_index, block_height, delta_nonstaked_amount, delta_staked_amount,
+            absolute_nonstaked_amount, absolute_staked_amount, next_block_height
+        FROM (
+            SELECT *,
+                LAG(event_index) OVER w AS previous_event_index,
+                LAG(block_height) OVER w AS previous_block_height,
+                LAG(absolute_nonstaked_amount) OVER w AS previous_nonstaked_amount,
+                LAG(absolute_staked_amount) OVER w AS previous_staked_amount,
+                LEAD(block_height) OVER w AS next_block_height
+            FROM near_balance_events
+            WHERE ($1::text IS NULL OR affected_account_id = $1)
+                AND ($2::numeric IS NULL OR block_height >= $2::numeric)
+                AND ($3::numeric IS NULL OR block_height <= $3::numeric)
+            WINDOW w AS (PARTITION BY affected_account_id ORDER BY event_index)
+        ) events
+        WHERE previous_event_index IS NOT NULL
+            AND (previous_nonstaked_amount + delta_nonstaked_amount <> absolute_nonstaked_amount
+                OR previous_staked_amount + delta_staked_amount <> absolute_staked_amount)
+        ORDER BY affected_account_id, event_index",
+    )
+    .bind(&range.account_id)
+    .bind(range.from_block.map(|height| height.to_string()))
+    .bind(range.to_block.map(|height| height.to_string()))
+    .fetch(pool);
+
+    let mut count = 0;
+    while let Some(continuity_break) = breaks.try_next().await? {
+        count += 1;
+        on_break(continuity_break).await?;
+    }
+    Ok(count)
+}
+
+impl ContinuityBreak {
+    /// Asks the archival node about the balances at the end of the blocks of both rows
+    pub(crate) async fn check_with_rpc(
+        &self,
+        json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    ) -> anyhow::Result<Verdict> {
+        let account_id = self.account_id.parse()?;
+        // The previous row is followed by the current one, so it's the last one in its block
+        // if the current row is in the other block
+        let previous = if self.previous_block_height != self.block_height {
+            rpc_matches(
+                json_rpc_client,
+                &account_id,
+                &self.previous_block_height,
+                (
+                    &self.previous_nonstaked_amount,
+                    &self.previous_staked_amount,
+                ),
+            )
+            .await?
+        } else {
+            None
+        };
+        let current = if self.next_block_height.as_ref() != Some(&self.block_height) {
+            rpc_matches(
+                json_rpc_client,
+                &account_id,
+                &self.block_height,
+                (
+                    &self.absolute_nonstaked_amount,
+                    &self.absolute_staked_amount,
+                ),
+            )
+            .await?
+        } else {
+            None
+        };
+        Ok(Verdict::from_matches(previous, current))
+    }
+}
+
+async fn rpc_matches(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    account_id: &near_primitives::types::AccountId,
+    block_height: &BigDecimal,
+    (nonstaked_amount, staked_amount): (&BigDecimal, &BigDecimal),
+) -> anyhow::Result<Option<bool>> {
+    let block_height = block_height
+        .to_u64()
+        .ok_or_else(|| anyhow::anyhow!("Invalid block height {}", block_height))?;
+    let (rpc_nonstaked_amount, rpc_staked_amount) =
+        match crate::db_adapters::balance_changes::get_account_view(
+            json_rpc_client,
+            account_id,
+            near_primitives::types::BlockId::Height(block_height),
+        )
+        .await
+        {
+            Ok(account_view) => (account_view.amount, account_view.locked),
+            Err(err) => match err.handler_error() {
+                Some(RpcQueryError::UnknownAccount { .. }) => (0, 0),
+                Some(RpcQueryError::UnknownBlock { .. }) => return Ok(None),
+                _ => return Err(err.into()),
+            },
+        };
+    Ok(Some(
+        nonstaked_amount.to_string() == rpc_nonstaked_amount.to_string()
+            && staked_amount.to_string() == rpc_staked_amount.to_string(),
+    ))
+}
+
+/// Compares the events of the block with `account_balances_latest` before they are stored.
+/// Called in the transaction of `postgres` sink. The accounts which already have later events, e.g. when the block
+/// is replayed, are not checked against the table
+pub(crate) async fn check_block(
+    connection: &mut sqlx::PgConnection,
+    events: &[NearBalanceEvent],
+) -> anyhow::Result<Vec<ContinuityBreak>> {
+    let account_ids: Vec<&str> = events
+        .iter()
+        .map(|event| event.affected_account_id.as_str())
+        .collect();
+    let previous: Vec<AccountBalanceLatest> = sqlx::query_as(
+        "SELECT account_id, nonstaked_amount, staked_amount, last_event_index, last_block_height
+        FROM account_balances_latest WHERE account_id = ANY($1)",
+    )
+    .bind(&account_ids)
+    .fetch_all(&mut *connection)
+    .await?;
+    let previous = previous
+        .into_iter()
+        .map(|balance| (balance.account_id.clone(), balance))
+        .collect();
+    Ok(breaks_in_order(previous, events))
+}
+
+fn breaks_in_order(
+    mut previous: HashMap<String, AccountBalanceLatest>,
+    events: &[NearBalanceEvent],
+) -> Vec<ContinuityBreak> {
+    let mut breaks = vec![];
+    for event in events {
+        if let Some(previous_balance) = previous.get(&event.affected_account_id) {
+            if previous_balance.last_event_index >= event.event_index {
+                continue;
+            }
+            if &previous_balance.nonstaked_amount + &event.delta_nonstaked_amount
+                != event.absolute_nonstaked_amount
+                || &previous_balance.staked_amount + &event.delta_staked_amount
+                    != event.absolute_staked_amount
+            {
+                breaks.push(ContinuityBreak {
+                    account_id: event.affected_account_id.clone(),
+                    previous_event_index: previous_balance.last_event_index.clone(),
+                    previous_block_height: previous_balance.last_block_height.clone(),
+                    previous_nonstaked_amount: previous_balance.nonstaked_amount.clone(),
+                    previous_staked_amount: previous_balance.staked_amount.clone(),
+                    event_index: event.event_index.clone(),
+                    block_height: event.block_height.clone(),
+                    delta_nonstaked_amount: event.delta_nonstaked_amount.clone(),
+                    delta_staked_amount: event.delta_staked_amount.clone(),
+                    absolute_nonstaked_amount: event.absolute_nonstaked_amount.clone(),
+                    absolute_staked_amount: event.absolute_staked_amount.clone(),
+                    next_block_height: None,
+                });
+            }
+        }
+        previous.extend(
+            AccountBalanceLatest::from_events(std::slice::from_ref(event))
+                .into_iter()
+                .map(|balance| (balance.account_id.clone(), balance)),
+        );
+    }
+    breaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    #[test]
+    fn finds_breaks_within_block() {
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let known = balance_event(&header, 0, "alice.near", 0, 100);
+        let latest = AccountBalanceLatest::from_events(std::slice::from_ref(&known));
+        let events = vec![
+            // Replayed, already known
+            known.clone(),
+            balance_event(&header, 1, "alice.near", -10, 90),
+            balance_event(&header, 2, "alice.near", -10, 70),
+            balance_event(&header, 3, "bob.near", 5, 5),
+        ];
+
+        let breaks = breaks_in_order(
+            HashMap::from([("alice.near".to_string(), latest[0].clone())]),
+            &events,
+        );
+        assert_eq!(breaks.len(), 1);
+        assert_eq!(breaks[0].previous_event_index, events[1].event_index);
+        assert_eq!(breaks[0].event_index, events[2].event_index);
+        assert_eq!(breaks[0].previous_nonstaked_amount, 90.into());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn walks_account_history() {
+        let (pool, _schema) = test_pool().await;
+        let account_id = "alice.near";
+        let first = block_header(1, 1_000_000_000);
+        let second = block_header(2, 2_000_000_000);
+        let events = vec![
+            balance_event(&first, 0, account_id, 10, 10),
+            balance_event(&first, 1, account_id, -5, 5),
+            // 3 tokens are lost between the blocks
+            balance_event(&second, 0, account_id, 1, 3),
+            balance_event(&second, 1, "bob.near", 1, 1),
+        ];
+        crate::models::chunked_insert(&pool, &events, 1)
+            .await
+            .unwrap();
+
+        let mut breaks = vec![];
+        let count = find_breaks(
+            &pool,
+            &VerifyRange {
+                from_block: Some(1),
+                to_block: Some(2),
+                ..Default::default()
+            },
+            |continuity_break| {
+                breaks.push(continuity_break);
+                async { Ok(()) }
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(breaks[0].account_id, account_id);
+        assert_eq!(breaks[0].previous_event_index, events[1].event_index);
+        assert_eq!(breaks[0].event_index, events[2].event_index);
+        assert_eq!(breaks[0].next_block_height, None);
+    }
+
+    #[test]
+    fn blames_the_side_which_disagrees_with_rpc() {
+        assert_eq!(
+            Verdict::from_matches(Some(true), Some(true)),
+            Verdict::MissingEvents
+        );
+        assert_eq!(
+            Verdict::from_matches(Some(false), None),
+            Verdict::PreviousWrong
+        );
+        assert_eq!(
+            Verdict::from_matches(Some(true), Some(false)),
+            Verdict::CurrentWrong
+        );
+        assert_eq!(
+            Verdict::from_matches(Some(false), Some(false)),
+            Verdict::BothWrong
+        );
+        assert_eq!(Verdict::from_matches(None, Some(true)), Verdict::Unknown);
+    }
+}
@@ -0,0 +1,84 @@
+use sqlx::Row;
+
+/// Blocks between two processed ones are missing, the heights in between may also be skipped by the chain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Gap {
+    /// The latest processed block before the gap
+    pub after_block: u64,
+    /// The processed block which refers to the missing one as its previous block
+    pub before_block: u64,
+}
+
+/// Compares `prev_block_height` of every processed block with the block processed before it.
+/// The blocks before the first processed block in the range are not checked
+pub(crate) async fn find_gaps(
+    pool: &sqlx::PgPool,
+    from_block: u64,
+    to_block: u64,
+) -> anyhow::Result<Vec<Gap>> {
+    sqlx::query(
+        "SELECT CAST(previous_processed AS TEXT), CAST(block_height AS TEXT)
+        FROM (
+            SELECT block_height, prev_block_height,
+                LAG(block_height) OVER (ORDER BY block_height) AS previous_processed
+            FROM near_balance_processed_blocks
+            WHERE block_height BETWEEN $1::numeric AND $2::numeric
+        ) blocks
+        WHERE previous_processed IS NOT NULL AND prev_block_height IS DISTINCT FROM previous_processed
+        ORDER BY block_height",
+    )
+    .bind(from_block.to_string())
+    .bind(to_block.to_string())
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|row| {
+        Ok(Gap {
+            after_block: row.get::<String, _>(0).parse()?,
+            before_block: row.get::<String, _>(1).parse()?,
+        })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::processed_blocks::NearBalanceProcessedBlock;
+    use crate::test_utils::{block_header, test_pool};
+
+    #[tokio::test]
+    #[ignore]
+    async fn finds_missing_blocks() {
+        let (pool, _schema) = test_pool().await;
+        // 2 is skipped by the chain, 4 is missed by the indexer
+        let mut blocks = [
+            block_header(1, 1_000_000_000),
+            block_header(3, 3_000_000_000),
+            block_header(5, 5_000_000_000),
+        ];
+        blocks[1].prev_height = Some(1);
+        crate::models::chunked_insert(
+            &pool,
+            &blocks
+                .iter()
+                .map(NearBalanceProcessedBlock::from_block)
+                .collect::<Vec<_>>(),
+            1,
+        )
+        .await
+        .unwrap();
+
+        let gaps = find_gaps(&pool, 1, 5).await.unwrap();
+        // The first block in the range refers to the block before the range, it's not checked
+        let first_gaps = find_gaps(&pool, 3, 5).await.unwrap();
+        assert_eq!(
+            gaps,
+            vec![Gap {
+                after_block: 3,
+                before_block: 5
+            }]
+        );
+        assert_eq!(first_gaps, gaps);
+    }
+}
@@ -1,3 +1,10 @@
+pub(crate) mod balance_at;
+pub(crate) mod balance_buckets;
 pub(crate) mod balance_changes;
+pub(crate) mod continuity;
+pub(crate) mod gaps;
+pub(crate) mod reindex;
+pub(crate) mod shadow_diff;
+pub(crate) mod supply_check;
 
 pub(crate) const CHUNK_SIZE_FOR_BATCH_INSERT: usize = 100;
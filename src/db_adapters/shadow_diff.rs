@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+
+use futures::TryStreamExt;
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::sinks::EventMessage;
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum DiffKind {
+    /// Only in `near_balance_events`
+    Missing,
+    /// Only in `near_balance_events_shadow`
+    Extra,
+    Changed,
+}
+
+/// Difference between the stored event and the shadow one with the same `event_index`
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub(crate) struct EventDiff {
+    pub event_index: String,
+    pub kind: DiffKind,
+    /// The fields which differ, for `CHANGED` only
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+    pub indexed: Option<EventMessage>,
+    pub shadow: Option<EventMessage>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct DiffSummary {
+    pub compared: usize,
+    pub missing: usize,
+    pub extra: usize,
+    pub changed: usize,
+}
+
+impl DiffSummary {
+    pub(crate) fn differences(&self) -> usize {
+        self.missing + self.extra + self.changed
+    }
+}
+
+/// None if both events are the same
+fn compare(
+    indexed: Option<&NearBalanceEvent>,
+    shadow: Option<&NearBalanceEvent>,
+) -> anyhow::Result<Option<EventDiff>> {
+    let indexed = indexed.map(EventMessage::from);
+    let shadow = shadow.map(EventMessage::from);
+    let (event_index, kind, fields) = match (&indexed, &shadow) {
+        (Some(indexed), Some(shadow)) => {
+            let shadow_fields = serde_json::to_value(shadow)?;
+            let fields: Vec<String> = serde_json::to_value(indexed)?
+                .as_object()
+                .ok_or_else(|| anyhow::anyhow!("Event should be a JSON object"))?
+                .iter()
+                .filter(|(field, value)| shadow_fields.get(field) != Some(value))
+                .map(|(field, _)| field.clone())
+                .collect();
+            if fields.is_empty() {
+                return Ok(None);
+            }
+            (indexed.event_index.clone(), DiffKind::Changed, fields)
+        }
+        (Some(indexed), None) => (indexed.event_index.clone(), DiffKind::Missing, vec![]),
+        (None, Some(shadow)) => (shadow.event_index.clone(), DiffKind::Extra, vec![]),
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(EventDiff {
+        event_index,
+        kind,
+        fields,
+        indexed,
+        shadow,
+    }))
+}
+
+/// Walks the events of both tables in the block range in `event_index` order and passes the differences to `on_diff`
+pub(crate) async fn diff_range<F>(
+    pool: &sqlx::PgPool,
+    from_block: u64,
+    to_block: u64,
+    mut on_diff: F,
+) -> anyhow::Result<DiffSummary>
+where
+    F: FnMut(EventDiff) -> anyhow::Result<()>,
+{
+    let query = |table: &str| {
+        format!(
+            "SELECT * FROM {}
+            WHERE block_height BETWEEN $1::numeric AND $2::numeric
+            ORDER BY event_index",
+            table
+        )
+    };
+    let (indexed_query, shadow_query) = (
+        query("near_balance_events"),
+        query("near_balance_events_shadow"),
+    );
+    let mut indexed = sqlx::query_as::<_, NearBalanceEvent>(&indexed_query)
+        .bind(from_block.to_string())
+        .bind(to_block.to_string())
+        .fetch(pool);
+    let mut shadow = sqlx::query_as::<_, NearBalanceEvent>(&shadow_query)
+        .bind(from_block.to_string())
+        .bind(to_block.to_string())
+        .fetch(pool);
+
+    let mut summary = DiffSummary::default();
+    let mut next_indexed = indexed.try_next().await?;
+    let mut next_shadow = shadow.try_next().await?;
+    loop {
+        let order = match (&next_indexed, &next_shadow) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(indexed), Some(shadow)) => indexed.event_index.cmp(&shadow.event_index),
+        };
+        let diff = match order {
+            Ordering::Less => compare(next_indexed.as_ref(), None)?,
+            Ordering::Greater => compare(None, next_shadow.as_ref())?,
+            Ordering::Equal => compare(next_indexed.as_ref(), next_shadow.as_ref())?,
+        };
+        if order != Ordering::Greater {
+            next_indexed = indexed.try_next().await?;
+        }
+        if order != Ordering::Less {
+            next_shadow = shadow.try_next().await?;
+        }
+
+        summary.compared += 1;
+        if let Some(diff) = diff {
+            match diff.kind {
+                DiffKind::Missing => summary.missing += 1,
+                DiffKind::Extra => summary.extra += 1,
+                DiffKind::Changed => summary.changed += 1,
+            }
+            on_diff(diff)?;
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    #[test]
+    fn lists_changed_fields() {
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let indexed = balance_event(&header, 0, "alice.near", -5, 10);
+        let mut shadow = indexed.clone();
+        assert_eq!(compare(Some(&indexed), Some(&shadow)).unwrap(), None);
+
+        shadow.cause = "TRANSACTION".to_string();
+        shadow.delta_nonstaked_amount = (-6).into();
+        let diff = compare(Some(&indexed), Some(&shadow)).unwrap().unwrap();
+        assert_eq!(diff.kind, DiffKind::Changed);
+        assert_eq!(diff.fields, ["cause", "delta_nonstaked_amount"]);
+
+        let diff = compare(None, Some(&shadow)).unwrap().unwrap();
+        assert_eq!(
+            (diff.kind, diff.event_index),
+            (DiffKind::Extra, shadow.event_index.to_string())
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn diffs_shadow_table() {
+        let (pool, schema) = test_pool().await;
+        let header = block_header(1, 1_000_000_000);
+        let same = balance_event(&header, 0, "alice.near", 1, 1);
+        let missing = balance_event(&header, 1, "bob.near", 2, 2);
+        let changed = balance_event(&header, 2, "carol.near", 3, 3);
+        let mut changed_shadow = changed.clone();
+        changed_shadow.delta_nonstaked_amount = 4.into();
+        let extra = balance_event(&header, 3, "dave.near", 5, 5);
+        crate::models::chunked_insert(&pool, &[same.clone(), missing, changed], 1)
+            .await
+            .unwrap();
+        let shadow_sink = crate::sinks::shadow::ShadowSink::connect(&schema.url)
+            .await
+            .unwrap();
+        crate::sinks::EventSink::store_block(&shadow_sink, &header, &[same, changed_shadow, extra])
+            .await
+            .unwrap();
+
+        let mut diffs = vec![];
+        let summary = diff_range(&pool, 1, 1, |diff| {
+            diffs.push((diff.kind, diff.fields));
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            diffs,
+            [
+                (DiffKind::Missing, vec![]),
+                (
+                    DiffKind::Changed,
+                    vec!["delta_nonstaked_amount".to_string()]
+                ),
+                (DiffKind::Extra, vec![]),
+            ]
+        );
+        assert_eq!(summary.compared, 4);
+        assert_eq!(summary.differences(), 3);
+    }
+}
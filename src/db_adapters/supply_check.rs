@@ -0,0 +1,155 @@
+use near_lake_framework::near_indexer_primitives::{self, views::BlockHeaderView, CryptoHash};
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::models::supply_mismatches::NearBalanceSupplyMismatch;
+
+/// Checks that every block's `total_supply` change is explained by its events, see `--supply-check`
+pub(crate) struct SupplyChecker {
+    /// Hash and `total_supply` of the last checked block, the blocks come one by one
+    previous: std::sync::Mutex<Option<(CryptoHash, u128)>>,
+    /// Mismatches are written here if it's set
+    pool: Option<sqlx::PgPool>,
+}
+
+impl SupplyChecker {
+    pub(crate) fn new(pool: Option<sqlx::PgPool>) -> Self {
+        Self {
+            previous: std::sync::Mutex::new(None),
+            pool,
+        }
+    }
+
+    pub(crate) async fn check(
+        &self,
+        shards: &[near_indexer_primitives::IndexerShard],
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+        json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    ) -> anyhow::Result<()> {
+        let previous = *self
+            .previous
+            .lock()
+            .expect("Supply checker lock is poisoned");
+        let previous_total_supply = match previous {
+            Some((hash, total_supply)) if hash == block_header.prev_hash => total_supply,
+            // The first block after the start
+            _ => get_total_supply_retriable(&block_header.prev_hash, json_rpc_client).await?,
+        };
+        *self
+            .previous
+            .lock()
+            .expect("Supply checker lock is poisoned") =
+            Some((block_header.hash, block_header.total_supply));
+
+        let check = NearBalanceSupplyMismatch::reconcile(
+            block_header,
+            previous_total_supply,
+            shards,
+            events,
+        )?;
+        if !check.is_mismatch() {
+            return Ok(());
+        }
+        crate::metrics::SUPPLY_MISMATCHES_TOTAL.inc();
+        tracing::warn!(
+            target: crate::LOGGING_PREFIX,
+            "Total supply of block {} changed by {} more than the events explain. Validators reward {}, burnt {}, all the deltas {}",
+            block_header.height,
+            check.difference,
+            check.validators_reward,
+            check.balance_burnt,
+            check.events_delta,
+        );
+        if let Some(pool) = &self.pool {
+            crate::models::chunked_insert(pool, &[check], crate::RETRY_COUNT).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn get_total_supply_retriable(
+    block_hash: &CryptoHash,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<u128> {
+    let mut interval = crate::INTERVAL;
+    let mut retry_attempt = 0usize;
+
+    loop {
+        if retry_attempt == crate::RETRY_COUNT {
+            anyhow::bail!(
+                "Failed to perform query to RPC after {} attempts. Stop trying.\nBlock {}",
+                crate::RETRY_COUNT,
+                block_hash
+            );
+        }
+        retry_attempt += 1;
+
+        let request = near_jsonrpc_client::methods::block::RpcBlockRequest {
+            block_reference: near_primitives::types::BlockReference::BlockId(
+                near_primitives::types::BlockId::Hash(*block_hash),
+            ),
+        };
+        match json_rpc_client.call(request).await {
+            Ok(block) => return Ok(block.header.total_supply),
+            Err(err) => {
+                tracing::error!(
+                    target: crate::LOGGING_PREFIX,
+                    "Failed to request block {} from RPC.{}\n Retrying in {} milliseconds...",
+                    block_hash,
+                    err,
+                    interval.as_millis(),
+                );
+                tokio::time::sleep(interval).await;
+                if interval < crate::MAX_DELAY_TIME {
+                    interval *= 2;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{balance_event, block_header, start_mock_rpc, test_pool};
+
+    #[tokio::test]
+    #[ignore]
+    async fn records_mismatches() {
+        let (pool, _schema) = test_pool().await;
+        let checker = SupplyChecker::new(Some(pool.clone()));
+        let first = block_header(1, 1_000_000_000);
+        let mut second = block_header(2, 2_000_000_000);
+        second.prev_hash = first.hash;
+        second.hash = CryptoHash::hash_bytes(b"2");
+        second.total_supply = first.total_supply + 7;
+        // The previous block is known, RPC is not asked
+        *checker.previous.lock().unwrap() = Some((first.hash, first.total_supply));
+        let json_rpc_client = start_mock_rpc(
+            near_primitives::types::BlockId::Hash(second.prev_hash),
+            Default::default(),
+        );
+
+        let events = [balance_event(&second, 0, "alice.near", 7, 7)];
+        checker
+            .check(&[], &second, &events, &json_rpc_client)
+            .await
+            .unwrap();
+        let mismatch: NearBalanceSupplyMismatch = sqlx::query_as(
+            "SELECT block_height, block_timestamp, previous_total_supply, total_supply, validators_reward,
+                balance_burnt, events_delta, difference
+             FROM near_balance_supply_mismatches",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            (mismatch.events_delta, mismatch.difference),
+            (7.into(), 7.into())
+        );
+        assert_eq!(
+            *checker.previous.lock().unwrap(),
+            Some((second.hash, second.total_supply))
+        );
+    }
+}
@@ -6,10 +6,15 @@ use futures::StreamExt;
 use near_lake_framework::near_indexer_primitives;
 use tokio::sync::Mutex;
 
+mod api;
+mod commands;
 mod configs;
 mod db_adapters;
 mod metrics;
 mod models;
+mod sinks;
+#[cfg(test)]
+mod test_utils;
 
 #[macro_use]
 extern crate lazy_static;
@@ -32,8 +37,32 @@ pub struct AccountWithBalance {
     pub balance: BalanceDetails,
 }
 
-pub type BalanceCache =
-    std::sync::Arc<Mutex<SizedCache<near_indexer_primitives::types::AccountId, BalanceDetails>>>;
+#[derive(Debug, Clone, Copy)]
+pub struct CachedBalance {
+    pub balance: BalanceDetails,
+    /// The block being indexed when the balance was cached
+    pub block_hash: near_indexer_primitives::CryptoHash,
+}
+
+/// The balances after the last seen change of the accounts, to avoid RPC queries for the previous balance
+pub struct BalanceCache {
+    pub(crate) balances:
+        Mutex<SizedCache<near_indexer_primitives::types::AccountId, CachedBalance>>,
+    /// Percent of the cache hits compared with RPC, see `--balance-cache-check-percent`
+    pub(crate) check_percent: u8,
+    /// Number of the cache hits so far, to pick the ones to check
+    pub(crate) hits: std::sync::atomic::AtomicU64,
+}
+
+impl BalanceCache {
+    pub fn new(size: usize, check_percent: u8) -> Self {
+        Self {
+            balances: Mutex::new(SizedCache::with_size(size)),
+            check_percent,
+            hits: std::sync::atomic::AtomicU64::new(0),
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -42,13 +71,41 @@ async fn main() -> anyhow::Result<()> {
     let opts = Opts::parse();
     let _worker_guard = init_tracing(opts.debug)?;
 
-    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
-    // TODO Error: while executing migrations: error returned from database: 1128 (HY000): Function 'near_indexer.GET_LOCK' is not defined
-    // sqlx::migrate!().run(&pool).await?;
+    if let Some(command) = &opts.command {
+        return commands::run(command, &opts).await;
+    }
+
+    if opts.graphql.graphql && !opts.api {
+        anyhow::bail!("--graphql requires --api");
+    }
+    if opts.supply_check.supply_check_table && !opts.supply_check.supply_check {
+        anyhow::bail!("--supply-check-table requires --supply-check");
+    }
+    let mut sinks = sinks::Sinks::from_config(&opts).await?;
+    let api = if opts.api {
+        let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+        let live_events = sinks::live::live_events();
+        sinks.push(Box::new(sinks::live::LiveSink::new(live_events.clone())));
+        let graphql = if opts.graphql.graphql {
+            Some(api::graphql::schema(pool.clone(), &opts.graphql))
+        } else {
+            None
+        };
+        Some(api::ApiState {
+            pool,
+            json_rpc_client: near_jsonrpc_client::JsonRpcClient::connect(
+                &opts.near_archival_rpc_url,
+            ),
+            live_events,
+            graphql,
+        })
+    } else {
+        None
+    };
 
     let start_block_height = match opts.start_block_height {
         Some(x) => x,
-        None => models::start_after_interruption(&pool).await?,
+        None => sinks.start_after_interruption().await?,
     };
     tracing::info!(
         target: LOGGING_PREFIX,
@@ -61,14 +118,31 @@ async fn main() -> anyhow::Result<()> {
     let (_lake_handle, stream) = near_lake_framework::streamer(config);
 
     // We want to prevent unnecessary RPC queries to find previous balance
-    let balances_cache: BalanceCache =
-        std::sync::Arc::new(Mutex::new(SizedCache::with_size(100_000)));
+    let balances_cache = BalanceCache::new(100_000, opts.balance_cache_check_percent);
 
     let json_rpc_client = near_jsonrpc_client::JsonRpcClient::connect(&opts.near_archival_rpc_url);
+    let supply_checker = if opts.supply_check.supply_check {
+        let pool = if opts.supply_check.supply_check_table {
+            Some(sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?)
+        } else {
+            None
+        };
+        Some(db_adapters::supply_check::SupplyChecker::new(pool))
+    } else {
+        None
+    };
+    let unexpected_causes = opts.unexpected_causes.clone();
     tokio::spawn(async move {
         let mut handlers = tokio_stream::wrappers::ReceiverStream::new(stream)
             .map(|streamer_message| {
-                handle_streamer_message(streamer_message, &pool, &balances_cache, &json_rpc_client)
+                handle_streamer_message(
+                    streamer_message,
+                    &sinks,
+                    &balances_cache,
+                    &json_rpc_client,
+                    &unexpected_causes,
+                    supply_checker.as_ref(),
+                )
             })
             .buffer_unordered(1usize);
 
@@ -96,14 +170,16 @@ async fn main() -> anyhow::Result<()> {
         Ok(()) // unreachable statement, loop above is endless
     });
 
-    metrics::init_metrics_server(opts.port).await
+    metrics::init_metrics_server(opts.port, api).await
 }
 
 async fn handle_streamer_message(
     streamer_message: near_indexer_primitives::StreamerMessage,
-    pool: &sqlx::Pool<sqlx::Postgres>,
+    sinks: &sinks::Sinks,
     balances_cache: &BalanceCache,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    unexpected_causes: &configs::UnexpectedCausesConfig,
+    supply_checker: Option<&db_adapters::supply_check::SupplyChecker>,
 ) -> anyhow::Result<u64> {
     metrics::BLOCK_PROCESSED_TOTAL.inc();
     // Prometheus Gauge Metric type do not support u64
@@ -111,11 +187,13 @@ async fn handle_streamer_message(
     metrics::LATEST_BLOCK_HEIGHT.set(i64::try_from(streamer_message.block.header.height)?);
 
     db_adapters::balance_changes::store_balance_changes(
-        pool,
+        sinks,
         &streamer_message.shards,
         &streamer_message.block.header,
         balances_cache,
         json_rpc_client,
+        unexpected_causes,
+        supply_checker,
     )
     .await?;
 
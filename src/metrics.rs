@@ -1,5 +1,5 @@
 use actix_web::{get, App, HttpServer, Responder};
-use prometheus::{Encoder, IntCounter, IntGauge, Opts};
+use prometheus::{Encoder, IntCounter, IntCounterVec, IntGauge, Opts};
 
 use crate::LOGGING_PREFIX;
 
@@ -12,6 +12,17 @@ fn try_create_int_counter(name: &str, help: &str) -> Result<IntCounter, promethe
     Ok(counter)
 }
 
+fn try_create_int_counter_vec(
+    name: &str,
+    help: &str,
+    labels: &[&str],
+) -> Result<IntCounterVec, prometheus::Error> {
+    let opts = Opts::new(name, help);
+    let counter = IntCounterVec::new(opts, labels)?;
+    prometheus::register(Box::new(counter.clone()))?;
+    Ok(counter)
+}
+
 fn try_create_int_gauge(name: &str, help: &str) -> Result<IntGauge, prometheus::Error> {
     let opts = Opts::new(name, help);
     let gauge = IntGauge::with_opts(opts)?;
@@ -30,6 +41,58 @@ lazy_static! {
         "Last seen block height by indexer"
     )
     .unwrap();
+    pub(crate) static ref WEBHOOK_DELIVERED_TOTAL: IntCounter = try_create_int_counter(
+        "indexer_balances_webhook_delivered_total",
+        "Webhook deliveries accepted by the receivers"
+    )
+    .unwrap();
+    pub(crate) static ref WEBHOOK_FAILED_ATTEMPTS_TOTAL: IntCounter = try_create_int_counter(
+        "indexer_balances_webhook_failed_attempts_total",
+        "Webhook delivery attempts which failed, including the ones retried later"
+    )
+    .unwrap();
+    pub(crate) static ref WEBHOOK_DEAD_LETTERS_TOTAL: IntCounter = try_create_int_counter(
+        "indexer_balances_webhook_dead_letters_total",
+        "Webhook deliveries which failed all the attempts and were put to `webhook_dead_letters`"
+    )
+    .unwrap();
+    pub(crate) static ref WEBHOOK_QUEUE_SIZE: IntGauge = try_create_int_gauge(
+        "indexer_balances_webhook_queue_size",
+        "Webhook deliveries waiting to be sent"
+    )
+    .unwrap();
+    pub(crate) static ref ALERTS_TOTAL: IntCounterVec = try_create_int_counter_vec(
+        "indexer_balances_alerts_total",
+        "Events matched by the alert rules",
+        &["rule"]
+    )
+    .unwrap();
+    pub(crate) static ref CONTINUITY_BREAKS_TOTAL: IntCounter = try_create_int_counter(
+        "indexer_balances_continuity_breaks_total",
+        "Events which do not continue the latest stored balance of the account, see `--postgres-verify-continuity`"
+    )
+    .unwrap();
+    pub(crate) static ref SUPPLY_MISMATCHES_TOTAL: IntCounter = try_create_int_counter(
+        "indexer_balances_supply_mismatches_total",
+        "Blocks where the change of total supply is not explained by the rewards and the burnt gas, see `--supply-check`"
+    )
+    .unwrap();
+    pub(crate) static ref CACHE_CHECKS_TOTAL: IntCounter = try_create_int_counter(
+        "indexer_balances_cache_checks_total",
+        "Cached balances compared with RPC, see `--balance-cache-check-percent`"
+    )
+    .unwrap();
+    pub(crate) static ref CACHE_MISMATCHES_TOTAL: IntCounter = try_create_int_counter(
+        "indexer_balances_cache_mismatches_total",
+        "Cached balances which differ from RPC, see `--balance-cache-check-percent`"
+    )
+    .unwrap();
+    pub(crate) static ref SKIPPED_CAUSES_TOTAL: IntCounterVec = try_create_int_counter_vec(
+        "indexer_balances_skipped_causes_total",
+        "Balance changes skipped because of their unexpected state change cause, see `--unexpected-cause-policy`",
+        &["cause"]
+    )
+    .unwrap();
 }
 
 #[get("/metrics")]
@@ -54,15 +117,24 @@ async fn get_metrics() -> impl Responder {
     }
 }
 
-pub(crate) async fn init_metrics_server(port: u16) -> anyhow::Result<()> {
+pub(crate) async fn init_metrics_server(
+    port: u16,
+    api: Option<crate::api::ApiState>,
+) -> anyhow::Result<()> {
     tracing::info!(
         target: LOGGING_PREFIX,
         "Starting metrics server on http://0.0.0.0:{port}/metrics"
     );
 
-    HttpServer::new(|| App::new().service(get_metrics))
-        .bind(("0.0.0.0", port))?
-        .run()
-        .await
-        .map_err(|e| anyhow::anyhow!("Error while executing HTTP Server: {}", e))
+    HttpServer::new(move || {
+        App::new().service(get_metrics).configure(|config| {
+            if let Some(api) = &api {
+                crate::api::configure(config, api.clone());
+            }
+        })
+    })
+    .bind(("0.0.0.0", port))?
+    .run()
+    .await
+    .map_err(|e| anyhow::anyhow!("Error while executing HTTP Server: {}", e))
 }
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+use sqlx::database::HasArguments;
+use sqlx::Arguments;
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::models::FieldCount;
+
+/// Current balance of the account, taken from its latest event
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, FieldCount)]
+pub struct AccountBalanceLatest {
+    pub account_id: String,
+    pub nonstaked_amount: BigDecimal,
+    pub staked_amount: BigDecimal,
+    pub last_event_index: BigDecimal,
+    pub last_block_height: BigDecimal,
+}
+
+impl AccountBalanceLatest {
+    /// One row per account, Postgres can't update the same row twice in one statement
+    pub(crate) fn from_events(events: &[NearBalanceEvent]) -> Vec<Self> {
+        let mut latest: HashMap<&str, &NearBalanceEvent> = HashMap::new();
+        for event in events {
+            let entry = latest.entry(&event.affected_account_id).or_insert(event);
+            if entry.event_index < event.event_index {
+                *entry = event;
+            }
+        }
+        let mut balances: Vec<Self> = latest
+            .into_values()
+            .map(|event| Self {
+                account_id: event.affected_account_id.clone(),
+                nonstaked_amount: event.absolute_nonstaked_amount.clone(),
+                staked_amount: event.absolute_staked_amount.clone(),
+                last_event_index: event.event_index.clone(),
+                last_block_height: event.block_height.clone(),
+            })
+            .collect();
+        // The same order of the row locks for the concurrent writers
+        balances.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+        balances
+    }
+}
+
+impl crate::models::SqlxMethods<sqlx::Postgres> for AccountBalanceLatest {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
+        args.add(&self.account_id);
+        args.add(&self.nonstaked_amount);
+        args.add(&self.staked_amount);
+        args.add(&self.last_event_index);
+        args.add(&self.last_block_height);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        // Replayed blocks do not move the balance back
+        Ok("INSERT INTO account_balances_latest (account_id, nonstaked_amount, staked_amount, last_event_index, last_block_height) VALUES ".to_owned()
+            + &crate::models::create_placeholders(count, AccountBalanceLatest::field_count())?
+            + " ON CONFLICT (account_id) DO UPDATE SET
+                nonstaked_amount = EXCLUDED.nonstaked_amount,
+                staked_amount = EXCLUDED.staked_amount,
+                last_event_index = EXCLUDED.last_event_index,
+                last_block_height = EXCLUDED.last_block_height
+            WHERE account_balances_latest.last_event_index < EXCLUDED.last_event_index")
+    }
+
+    fn name() -> String {
+        "account_balances_latest".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{balance_event, block_header};
+
+    #[test]
+    fn takes_latest_event_of_each_account() {
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let events = vec![
+            balance_event(&header, 0, "bob.near", 5, 15),
+            balance_event(&header, 2, "alice.near", -3, 7),
+            balance_event(&header, 1, "alice.near", -5, 10),
+        ];
+        let balances = AccountBalanceLatest::from_events(&events);
+        assert_eq!(
+            balances
+                .iter()
+                .map(|balance| (
+                    balance.account_id.as_str(),
+                    balance.nonstaked_amount.clone()
+                ))
+                .collect::<Vec<_>>(),
+            vec![("alice.near", 7.into()), ("bob.near", 15.into())]
+        );
+        assert_eq!(balances[0].last_event_index, events[1].event_index);
+    }
+}
@@ -0,0 +1,38 @@
+use bigdecimal::BigDecimal;
+use sqlx::database::HasArguments;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+/// Event matched by the alert rule
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, FieldCount)]
+pub struct NearBalanceAlert {
+    pub rule_name: String,
+    pub event_index: BigDecimal,
+    pub block_height: BigDecimal,
+    pub affected_account_id: String,
+    pub delta_nonstaked_amount: BigDecimal,
+    pub delta_staked_amount: BigDecimal,
+}
+
+impl crate::models::SqlxMethods<sqlx::Postgres> for NearBalanceAlert {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
+        args.add(&self.rule_name);
+        args.add(&self.event_index);
+        args.add(&self.block_height);
+        args.add(&self.affected_account_id);
+        args.add(&self.delta_nonstaked_amount);
+        args.add(&self.delta_staked_amount);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        // `created_at` is filled by the DB
+        Ok("INSERT INTO near_balance_alerts (rule_name, event_index, block_height, affected_account_id, delta_nonstaked_amount, delta_staked_amount) VALUES ".to_owned()
+            + &crate::models::create_placeholders(count, NearBalanceAlert::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "near_balance_alerts".to_string()
+    }
+}
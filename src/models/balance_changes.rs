@@ -1,9 +1,13 @@
 use bigdecimal::BigDecimal;
+use num_traits::ToPrimitive;
+use sqlx::database::HasArguments;
 use sqlx::Arguments;
 
 use crate::models::FieldCount;
 
-#[derive(Debug, sqlx::FromRow, FieldCount)]
+#[derive(
+    Debug, Clone, PartialEq, sqlx::FromRow, serde::Serialize, serde::Deserialize, FieldCount,
+)]
 pub struct NearBalanceEvent {
     pub event_index: BigDecimal,
     pub block_timestamp: BigDecimal,
@@ -19,10 +23,18 @@ pub struct NearBalanceEvent {
     pub absolute_nonstaked_amount: BigDecimal,
     pub delta_staked_amount: BigDecimal,
     pub absolute_staked_amount: BigDecimal,
+    /// The state change in JSON, for `UNKNOWN` cause only.
+    /// `jsonl`, `csv`, `parquet` and `clickhouse` sinks do not write it
+    #[serde(skip)]
+    #[sqlx(default)]
+    pub cause_payload: Option<String>,
 }
 
-impl crate::models::SqlxMethods for NearBalanceEvent {
-    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+/// The columns in the order of `add_to_args`, the tables got `cause_payload` after the others
+pub(crate) const INSERT_COLUMNS: &str = "(event_index, block_timestamp, block_height, receipt_id, transaction_hash, affected_account_id, involved_account_id, direction, cause, status, delta_nonstaked_amount, absolute_nonstaked_amount, delta_staked_amount, absolute_staked_amount, cause_payload)";
+
+impl crate::models::SqlxMethods<sqlx::Postgres> for NearBalanceEvent {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
         args.add(&self.event_index);
         args.add(&self.block_timestamp);
         args.add(&self.block_height);
@@ -37,15 +49,48 @@ impl crate::models::SqlxMethods for NearBalanceEvent {
         args.add(&self.absolute_nonstaked_amount);
         args.add(&self.delta_staked_amount);
         args.add(&self.absolute_staked_amount);
+        args.add(&self.cause_payload);
     }
 
     fn insert_query(count: usize) -> anyhow::Result<String> {
-        Ok("INSERT INTO near_balance_events VALUES ".to_owned()
-            + &crate::models::create_placeholders(count, NearBalanceEvent::field_count())?
-            + " ON CONFLICT DO NOTHING")
+        Ok(
+            format!("INSERT INTO near_balance_events {} VALUES ", INSERT_COLUMNS)
+                + &crate::models::create_placeholders(count, NearBalanceEvent::field_count())?
+                + " ON CONFLICT DO NOTHING",
+        )
     }
 
     fn name() -> String {
         "near_balance_events".to_string()
     }
 }
+
+// SQLite has no type for 40-digit numbers, so we store them as text.
+// Heights and timestamps fit into INTEGER
+impl crate::models::SqlxMethods<sqlx::Sqlite> for NearBalanceEvent {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Sqlite as HasArguments<'q>>::Arguments) {
+        args.add(self.event_index.to_string());
+        args.add(self.block_timestamp.to_i64());
+        args.add(self.block_height.to_i64());
+        args.add(&self.receipt_id);
+        args.add(&self.transaction_hash);
+        args.add(&self.affected_account_id);
+        args.add(&self.involved_account_id);
+        args.add(&self.direction);
+        args.add(&self.cause);
+        args.add(&self.status);
+        args.add(self.delta_nonstaked_amount.to_string());
+        args.add(self.absolute_nonstaked_amount.to_string());
+        args.add(self.delta_staked_amount.to_string());
+        args.add(self.absolute_staked_amount.to_string());
+        args.add(&self.cause_payload);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        <Self as crate::models::SqlxMethods<sqlx::Postgres>>::insert_query(count)
+    }
+
+    fn name() -> String {
+        <Self as crate::models::SqlxMethods<sqlx::Postgres>>::name()
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, Zero};
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+use sqlx::database::HasArguments;
+use sqlx::Arguments;
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::models::{Cause, FieldCount, PrintEnum};
+
+/// Network-wide summary of the balance changes in the block
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, FieldCount)]
+pub struct NearBalanceBlockStats {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub nonstaked_inflow: BigDecimal,
+    pub nonstaked_outflow: BigDecimal,
+    pub staked_inflow: BigDecimal,
+    pub staked_outflow: BigDecimal,
+    pub validators_reward: BigDecimal,
+    pub contract_reward: BigDecimal,
+    pub affected_accounts_count: i32,
+    pub total_supply: BigDecimal,
+}
+
+impl NearBalanceBlockStats {
+    /// Written for the blocks without events too, they still have `total_supply`
+    pub(crate) fn from_block(
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<Self> {
+        let mut stats = Self {
+            block_height: block_header.height.into(),
+            block_timestamp: block_header.timestamp_nanosec.into(),
+            nonstaked_inflow: BigDecimal::zero(),
+            nonstaked_outflow: BigDecimal::zero(),
+            staked_inflow: BigDecimal::zero(),
+            staked_outflow: BigDecimal::zero(),
+            validators_reward: BigDecimal::zero(),
+            contract_reward: BigDecimal::zero(),
+            affected_accounts_count: 0,
+            total_supply: BigDecimal::from_str(&block_header.total_supply.to_string())?,
+        };
+        let add_delta = |inflow: &mut BigDecimal, outflow: &mut BigDecimal, delta: &BigDecimal| {
+            if delta > &BigDecimal::zero() {
+                *inflow += delta;
+            } else {
+                *outflow -= delta;
+            }
+        };
+
+        let mut affected_accounts = HashSet::new();
+        for event in events {
+            add_delta(
+                &mut stats.nonstaked_inflow,
+                &mut stats.nonstaked_outflow,
+                &event.delta_nonstaked_amount,
+            );
+            add_delta(
+                &mut stats.staked_inflow,
+                &mut stats.staked_outflow,
+                &event.delta_staked_amount,
+            );
+            let delta = &event.delta_nonstaked_amount + &event.delta_staked_amount;
+            if event.cause == Cause::ValidatorsReward.print() {
+                stats.validators_reward += delta;
+            } else if event.cause == Cause::ContractReward.print() {
+                stats.contract_reward += delta;
+            }
+            affected_accounts.insert(&event.affected_account_id);
+        }
+        stats.affected_accounts_count = affected_accounts.len() as i32;
+        Ok(stats)
+    }
+}
+
+impl crate::models::SqlxMethods<sqlx::Postgres> for NearBalanceBlockStats {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.nonstaked_inflow);
+        args.add(&self.nonstaked_outflow);
+        args.add(&self.staked_inflow);
+        args.add(&self.staked_outflow);
+        args.add(&self.validators_reward);
+        args.add(&self.contract_reward);
+        args.add(self.affected_accounts_count);
+        args.add(&self.total_supply);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok("INSERT INTO near_balance_block_stats (block_height, block_timestamp, nonstaked_inflow, nonstaked_outflow, staked_inflow, staked_outflow, validators_reward, contract_reward, affected_accounts_count, total_supply) VALUES ".to_owned()
+            + &crate::models::create_placeholders(count, NearBalanceBlockStats::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "near_balance_block_stats".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{balance_event, block_header};
+
+    #[test]
+    fn sums_block_deltas() {
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let mut reward = balance_event(&header, 0, "validator.near", 0, 100);
+        reward.cause = Cause::ValidatorsReward.print().to_string();
+        reward.delta_staked_amount = 40.into();
+        let mut contract_reward = balance_event(&header, 1, "contract.near", 3, 50);
+        contract_reward.cause = Cause::ContractReward.print().to_string();
+        let events = vec![
+            reward,
+            contract_reward,
+            balance_event(&header, 2, "alice.near", -10, 0),
+            balance_event(&header, 3, "bob.near", 7, 7),
+            balance_event(&header, 4, "alice.near", 5, 5),
+        ];
+
+        let stats = NearBalanceBlockStats::from_block(&header, &events).unwrap();
+        assert_eq!(
+            (
+                stats.nonstaked_inflow,
+                stats.nonstaked_outflow,
+                stats.staked_inflow,
+                stats.staked_outflow
+            ),
+            (15.into(), 10.into(), 40.into(), 0.into())
+        );
+        assert_eq!(
+            (stats.validators_reward, stats.contract_reward),
+            (40.into(), 3.into())
+        );
+        assert_eq!(stats.affected_accounts_count, 4);
+        assert_eq!(
+            stats.total_supply.to_string(),
+            header.total_supply.to_string()
+        );
+    }
+}
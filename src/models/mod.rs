@@ -1,44 +1,88 @@
 use std::fmt::Write;
 
-use bigdecimal::BigDecimal;
 use futures::future::try_join_all;
 use near_lake_framework::near_indexer_primitives::views::ExecutionStatusView;
 
-use num_traits::ToPrimitive;
-use sqlx::{Arguments, Row};
+use sqlx::database::HasArguments;
+use sqlx::{Arguments, Database, Executor, IntoArguments, Row};
 
 pub(crate) use indexer_balances::FieldCount;
+pub(crate) mod account_balances_latest;
+pub(crate) mod alerts;
 pub(crate) mod balance_changes;
+pub(crate) mod block_stats;
+pub(crate) mod outbox;
+pub(crate) mod processed_blocks;
+pub(crate) mod supply_mismatches;
+pub(crate) mod webhook_dead_letters;
 
 pub trait FieldCount {
     /// Get the number of fields on a struct.
     fn field_count() -> usize;
 }
 
-pub trait SqlxMethods {
-    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments);
+/// Describes how to store the struct to the database of the given kind
+pub trait SqlxMethods<DB: Database> {
+    fn add_to_args<'q>(&'q self, args: &mut <DB as HasArguments<'q>>::Arguments);
 
     fn insert_query(count: usize) -> anyhow::Result<String>;
 
     fn name() -> String;
 }
 
-pub async fn chunked_insert<T: SqlxMethods + std::fmt::Debug>(
-    pool: &sqlx::Pool<sqlx::Postgres>,
+pub async fn chunked_insert<DB, T>(
+    pool: &sqlx::Pool<DB>,
     items: &[T],
     retry_count: usize,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<()>
+where
+    DB: Database,
+    T: SqlxMethods<DB> + std::fmt::Debug + Sync,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
     let futures = items
         .chunks(crate::db_adapters::CHUNK_SIZE_FOR_BATCH_INSERT)
         .map(|items_part| insert_retry_or_panic(pool, items_part, retry_count));
     try_join_all(futures).await.map(|_| ())
 }
 
-async fn insert_retry_or_panic<T: SqlxMethods + std::fmt::Debug>(
-    pool: &sqlx::Pool<sqlx::Postgres>,
+/// Inserts the items through the given connection, without retries.
+/// Used inside the transactions: if something fails, the whole transaction should be retried
+pub(crate) async fn chunked_insert_with_connection<DB, T>(
+    connection: &mut DB::Connection,
+    items: &[T],
+) -> anyhow::Result<()>
+where
+    DB: Database,
+    T: SqlxMethods<DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
+    for items_part in items.chunks(crate::db_adapters::CHUNK_SIZE_FOR_BATCH_INSERT) {
+        let query = T::insert_query(items_part.len())?;
+        let mut args = <DB as HasArguments>::Arguments::default();
+        for item in items_part {
+            item.add_to_args(&mut args);
+        }
+        sqlx::query_with(&query, args)
+            .execute(&mut *connection)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn insert_retry_or_panic<DB, T>(
+    pool: &sqlx::Pool<DB>,
     items: &[T],
     retry_count: usize,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<()>
+where
+    DB: Database,
+    T: SqlxMethods<DB> + std::fmt::Debug,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
     let mut interval = crate::INTERVAL;
     let mut retry_attempt = 0usize;
     let query = T::insert_query(items.len())?;
@@ -52,7 +96,7 @@ async fn insert_retry_or_panic<T: SqlxMethods + std::fmt::Debug>(
         }
         retry_attempt += 1;
 
-        let mut args = sqlx::postgres::PgArguments::default();
+        let mut args = <DB as HasArguments>::Arguments::default();
         for item in items {
             item.add_to_args(&mut args);
         }
@@ -78,12 +122,19 @@ async fn insert_retry_or_panic<T: SqlxMethods + std::fmt::Debug>(
     Ok(())
 }
 
-pub async fn select_retry_or_panic(
-    pool: &sqlx::Pool<sqlx::Postgres>,
+/// Runs the statement which returns no rows, e.g. UPDATE or upsert
+pub(crate) async fn execute_retry_or_panic<DB>(
+    pool: &sqlx::Pool<DB>,
     query: &str,
     substitution_items: &[String],
     retry_count: usize,
-) -> anyhow::Result<Vec<sqlx::postgres::PgRow>> {
+) -> anyhow::Result<()>
+where
+    DB: Database,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> String: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+{
     let mut interval = crate::INTERVAL;
     let mut retry_attempt = 0usize;
 
@@ -96,9 +147,57 @@ pub async fn select_retry_or_panic(
         }
         retry_attempt += 1;
 
-        let mut args = sqlx::postgres::PgArguments::default();
+        let mut args = <DB as HasArguments>::Arguments::default();
         for item in substitution_items {
-            args.add(item);
+            args.add(item.clone());
+        }
+
+        match sqlx::query_with(query, args).execute(pool).await {
+            Ok(_) => return Ok(()),
+            Err(async_error) => {
+                tracing::error!(
+                    target: crate::LOGGING_PREFIX,
+                    "Error occurred during {}:\nFailed query:\n{}\n Retrying in {} milliseconds...",
+                    async_error,
+                    query,
+                    interval.as_millis(),
+                );
+                tokio::time::sleep(interval).await;
+                if interval < crate::MAX_DELAY_TIME {
+                    interval *= 2;
+                }
+            }
+        }
+    }
+}
+
+pub async fn select_retry_or_panic<DB>(
+    pool: &sqlx::Pool<DB>,
+    query: &str,
+    substitution_items: &[String],
+    retry_count: usize,
+) -> anyhow::Result<Vec<DB::Row>>
+where
+    DB: Database,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> String: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+{
+    let mut interval = crate::INTERVAL;
+    let mut retry_attempt = 0usize;
+
+    loop {
+        if retry_attempt == retry_count {
+            return Err(anyhow::anyhow!(
+                "Failed to perform query to database after {} attempts. Stop trying.",
+                retry_count
+            ));
+        }
+        retry_attempt += 1;
+
+        let mut args = <DB as HasArguments>::Arguments::default();
+        for item in substitution_items {
+            args.add(item.clone());
         }
 
         match sqlx::query_with(query, args).fetch_all(pool).await {
@@ -121,20 +220,33 @@ pub async fn select_retry_or_panic(
     }
 }
 
-pub(crate) async fn start_after_interruption(
-    pool: &sqlx::Pool<sqlx::Postgres>,
-) -> anyhow::Result<u64> {
-    let query = "SELECT max(block_height) FROM near_balance_events";
+/// The indexer restarts this many blocks before the latest stored block to be sure we haven't missed anything
+pub(crate) const RESTART_OVERLAP_BLOCKS: u64 = 1000;
+
+// Returns None when the DB is empty
+pub(crate) async fn start_after_interruption<DB>(
+    pool: &sqlx::Pool<DB>,
+) -> anyhow::Result<Option<u64>>
+where
+    DB: Database,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> String: sqlx::Encode<'q, DB> + sqlx::Decode<'q, DB> + sqlx::Type<DB>,
+    usize: sqlx::ColumnIndex<DB::Row>,
+{
+    // Text is the only type for the big numbers which all the DBs agree on
+    let query = "SELECT CAST(max(block_height) AS TEXT) FROM near_balance_events";
 
     let res = select_retry_or_panic(pool, query, &[], 10).await?;
-    Ok(res
-        .first()
-        .map(|value| value.get::<BigDecimal, _>(0))
-        .expect("`START_BLOCK_HEIGHT` should be provided when the DB is empty")
-        .to_u64()
-        .expect("height should be positive")
-        // We start 1000 blocks before the latest block in the DB to be sure we haven't missed anything
-        .saturating_sub(1000))
+    match res.first().and_then(|row| row.get::<Option<String>, _>(0)) {
+        Some(height) => Ok(Some(
+            height
+                .parse::<u64>()
+                .expect("height should be positive")
+                .saturating_sub(RESTART_OVERLAP_BLOCKS),
+        )),
+        None => Ok(None),
+    }
 }
 
 // Generates `($1, $2), ($3, $4)`
@@ -215,6 +327,8 @@ pub(crate) enum Cause {
     Transaction,
     Receipt,
     ContractReward,
+    /// State change cause we do not expect to change the balances, see `--unexpected-cause-policy`
+    Unknown,
 }
 
 impl PrintEnum for Cause {
@@ -224,6 +338,7 @@ impl PrintEnum for Cause {
             Cause::Transaction => "TRANSACTION",
             Cause::Receipt => "RECEIPT",
             Cause::ContractReward => "CONTRACT_REWARD",
+            Cause::Unknown => "UNKNOWN",
         }
     }
 }
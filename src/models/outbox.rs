@@ -0,0 +1,55 @@
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+use sqlx::database::HasArguments;
+use sqlx::Arguments;
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::models::FieldCount;
+
+/// Record about the stored block, written in the same transaction with its events.
+/// It's also the payload of `NOTIFY`
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, serde::Serialize, FieldCount)]
+pub struct NearBalanceEventsOutbox {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub first_event_index: BigDecimal,
+    pub last_event_index: BigDecimal,
+    pub events_count: i32,
+}
+
+impl NearBalanceEventsOutbox {
+    /// None for the blocks without events, there is nothing to fetch for them
+    pub(crate) fn from_block(
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> Option<Self> {
+        Some(Self {
+            block_height: block_header.height.into(),
+            block_timestamp: block_header.timestamp_nanosec.into(),
+            first_event_index: events.iter().map(|e| &e.event_index).min()?.clone(),
+            last_event_index: events.iter().map(|e| &e.event_index).max()?.clone(),
+            events_count: events.len() as i32,
+        })
+    }
+}
+
+impl crate::models::SqlxMethods<sqlx::Postgres> for NearBalanceEventsOutbox {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.first_event_index);
+        args.add(&self.last_event_index);
+        args.add(self.events_count);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        // `created_at` is filled by the DB
+        Ok("INSERT INTO near_balance_events_outbox (block_height, block_timestamp, first_event_index, last_event_index, events_count) VALUES ".to_owned()
+            + &crate::models::create_placeholders(count, NearBalanceEventsOutbox::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "near_balance_events_outbox".to_string()
+    }
+}
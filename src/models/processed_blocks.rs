@@ -0,0 +1,42 @@
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+use sqlx::database::HasArguments;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+/// Record about the block stored by `postgres` sink, with or without events
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, FieldCount)]
+pub struct NearBalanceProcessedBlock {
+    pub block_height: BigDecimal,
+    pub prev_block_height: Option<BigDecimal>,
+    pub block_hash: String,
+}
+
+impl NearBalanceProcessedBlock {
+    pub(crate) fn from_block(block_header: &BlockHeaderView) -> Self {
+        Self {
+            block_height: block_header.height.into(),
+            prev_block_height: block_header.prev_height.map(Into::into),
+            block_hash: block_header.hash.to_string(),
+        }
+    }
+}
+
+impl crate::models::SqlxMethods<sqlx::Postgres> for NearBalanceProcessedBlock {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
+        args.add(&self.block_height);
+        args.add(&self.prev_block_height);
+        args.add(&self.block_hash);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok("INSERT INTO near_balance_processed_blocks (block_height, prev_block_height, block_hash) VALUES ".to_owned()
+            + &crate::models::create_placeholders(count, NearBalanceProcessedBlock::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "near_balance_processed_blocks".to_string()
+    }
+}
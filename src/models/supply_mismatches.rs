@@ -0,0 +1,134 @@
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, Zero};
+use near_lake_framework::near_indexer_primitives::{self, views::BlockHeaderView};
+use sqlx::database::HasArguments;
+use sqlx::Arguments;
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::models::block_stats::NearBalanceBlockStats;
+use crate::models::FieldCount;
+
+/// Reconciliation of `total_supply` change in the block with its events.
+/// Only the mismatches are written to the DB
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, FieldCount)]
+pub struct NearBalanceSupplyMismatch {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub previous_total_supply: BigDecimal,
+    pub total_supply: BigDecimal,
+    pub validators_reward: BigDecimal,
+    pub balance_burnt: BigDecimal,
+    pub events_delta: BigDecimal,
+    pub difference: BigDecimal,
+}
+
+impl NearBalanceSupplyMismatch {
+    /// The block changes the supply by the minted validator rewards minus the tokens burnt in its chunks.
+    /// The minted tokens go to the accounts with `ValidatorAccountsUpdate` cause,
+    /// so the difference is not zero when we missed some of these changes
+    pub(crate) fn reconcile(
+        block_header: &BlockHeaderView,
+        previous_total_supply: u128,
+        shards: &[near_indexer_primitives::IndexerShard],
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<Self> {
+        let stats = NearBalanceBlockStats::from_block(block_header, events)?;
+        let balance_burnt: u128 = shards
+            .iter()
+            .filter_map(|shard| shard.chunk.as_ref())
+            .filter(|chunk| chunk.header.height_included == block_header.height)
+            .map(|chunk| chunk.header.balance_burnt)
+            .sum();
+        let previous_total_supply = BigDecimal::from_str(&previous_total_supply.to_string())?;
+        let balance_burnt = BigDecimal::from_str(&balance_burnt.to_string())?;
+        let difference = &stats.total_supply
+            - &previous_total_supply
+            - (&stats.validators_reward - &balance_burnt);
+
+        Ok(Self {
+            block_height: stats.block_height,
+            block_timestamp: stats.block_timestamp,
+            previous_total_supply,
+            total_supply: stats.total_supply,
+            validators_reward: stats.validators_reward,
+            balance_burnt,
+            events_delta: stats.nonstaked_inflow - stats.nonstaked_outflow + stats.staked_inflow
+                - stats.staked_outflow,
+            difference,
+        })
+    }
+
+    pub(crate) fn is_mismatch(&self) -> bool {
+        !self.difference.is_zero()
+    }
+}
+
+impl crate::models::SqlxMethods<sqlx::Postgres> for NearBalanceSupplyMismatch {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.previous_total_supply);
+        args.add(&self.total_supply);
+        args.add(&self.validators_reward);
+        args.add(&self.balance_burnt);
+        args.add(&self.events_delta);
+        args.add(&self.difference);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok("INSERT INTO near_balance_supply_mismatches (block_height, block_timestamp, previous_total_supply, total_supply, validators_reward, balance_burnt, events_delta, difference) VALUES ".to_owned()
+            + &crate::models::create_placeholders(count, NearBalanceSupplyMismatch::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "near_balance_supply_mismatches".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Cause, PrintEnum};
+    use crate::test_utils::{balance_event, block_header};
+
+    #[test]
+    fn reconciles_rewards_and_burnt_gas() {
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let mut reward = balance_event(&header, 0, "validator.near", 0, 100);
+        reward.cause = Cause::ValidatorsReward.print().to_string();
+        reward.delta_staked_amount = 40.into();
+        let events = vec![reward, balance_event(&header, 1, "alice.near", -10, 0)];
+        // Minted 40, burnt 15
+        let previous_total_supply = header.total_supply - 25;
+
+        let check =
+            NearBalanceSupplyMismatch::reconcile(&header, previous_total_supply, &[], &events)
+                .unwrap();
+        // There are no chunks, so nothing is burnt and 15 tokens are not explained
+        assert_eq!(check.validators_reward, 40.into());
+        assert_eq!(check.events_delta, 30.into());
+        assert_eq!(check.difference, BigDecimal::from(-15));
+        assert!(check.is_mismatch());
+
+        let mut fixture: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/balance_changes/validators_reward.json"
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        let mut shards: Vec<near_indexer_primitives::IndexerShard> =
+            serde_json::from_value(fixture["streamer_message"]["shards"].take()).unwrap();
+        let chunk = shards[0].chunk.as_mut().unwrap();
+        chunk.header.height_included = header.height;
+        chunk.header.balance_burnt = 15;
+        let check =
+            NearBalanceSupplyMismatch::reconcile(&header, previous_total_supply, &shards, &events)
+                .unwrap();
+        assert_eq!(check.balance_burnt, 15.into());
+        assert!(!check.is_mismatch());
+    }
+}
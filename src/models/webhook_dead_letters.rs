@@ -0,0 +1,37 @@
+use bigdecimal::BigDecimal;
+use sqlx::database::HasArguments;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+/// Webhook delivery which failed all the attempts
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, FieldCount)]
+pub struct WebhookDeadLetter {
+    pub url: String,
+    pub event_index: BigDecimal,
+    pub affected_account_id: String,
+    pub payload: String,
+    pub error: String,
+    pub attempts: i32,
+}
+
+impl crate::models::SqlxMethods<sqlx::Postgres> for WebhookDeadLetter {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
+        args.add(&self.url);
+        args.add(&self.event_index);
+        args.add(&self.affected_account_id);
+        args.add(&self.payload);
+        args.add(&self.error);
+        args.add(self.attempts);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        // `id` and `created_at` are filled by the DB
+        Ok("INSERT INTO webhook_dead_letters (url, event_index, affected_account_id, payload, error, attempts) VALUES ".to_owned()
+            + &crate::models::create_placeholders(count, WebhookDeadLetter::field_count())?)
+    }
+
+    fn name() -> String {
+        "webhook_dead_letters".to_string()
+    }
+}
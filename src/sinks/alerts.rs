@@ -0,0 +1,385 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+
+use crate::models::alerts::NearBalanceAlert;
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::sinks::webhooks::{DeliveryQueue, Webhook};
+use crate::sinks::EventMessage;
+
+/// Amount in yoctoNEAR, or in NEAR with the suffix: `"100000 NEAR"`
+#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+struct Amount(BigDecimal);
+
+impl TryFrom<String> for Amount {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = |value: &str| {
+            BigDecimal::from_str(value.trim())
+                .map_err(|err| format!("Failed to parse amount {}: {}", value, err))
+        };
+        match value.strip_suffix("NEAR") {
+            Some(near) => Ok(Self(parse(near)? * BigDecimal::new(1.into(), -24))),
+            None => Ok(Self(parse(&value)?)),
+        }
+    }
+}
+
+/// All the given bounds should hold
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Condition {
+    /// Compare the absolute value, useful for the deltas
+    #[serde(default)]
+    abs: bool,
+    gt: Option<Amount>,
+    gte: Option<Amount>,
+    lt: Option<Amount>,
+    lte: Option<Amount>,
+    eq: Option<Amount>,
+}
+
+impl Condition {
+    fn matches(&self, value: &BigDecimal) -> bool {
+        let value = if self.abs { value.abs() } else { value.clone() };
+        self.gt.as_ref().map_or(true, |bound| value > bound.0)
+            && self.gte.as_ref().map_or(true, |bound| value >= bound.0)
+            && self.lt.as_ref().map_or(true, |bound| value < bound.0)
+            && self.lte.as_ref().map_or(true, |bound| value <= bound.0)
+            && self.eq.as_ref().map_or(true, |bound| value == bound.0)
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Output {
+    /// Warning in the indexer logs
+    Log,
+    /// Row in `near_balance_alerts`
+    Table,
+    /// POST of `{"rule": "...", "event": {...}}`, delivered the same way `webhooks` sink does it
+    Webhook(Arc<Webhook>),
+}
+
+/// Entry of the rules file. The event matches the rule if it matches all the given fields
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    name: String,
+    /// `affected_account_id` is one of them
+    #[serde(default)]
+    accounts: HashSet<String>,
+    direction: Option<String>,
+    cause: Option<String>,
+    delta_nonstaked_amount: Option<Condition>,
+    absolute_nonstaked_amount: Option<Condition>,
+    delta_staked_amount: Option<Condition>,
+    absolute_staked_amount: Option<Condition>,
+    outputs: Vec<Output>,
+}
+
+impl Rule {
+    fn matches(&self, event: &NearBalanceEvent) -> bool {
+        let condition_matches = |condition: &Option<Condition>, value: &BigDecimal| {
+            condition
+                .as_ref()
+                .map_or(true, |condition| condition.matches(value))
+        };
+        (self.accounts.is_empty() || self.accounts.contains(&event.affected_account_id))
+            && self
+                .direction
+                .as_ref()
+                .map_or(true, |direction| direction == &event.direction)
+            && self
+                .cause
+                .as_ref()
+                .map_or(true, |cause| cause == &event.cause)
+            && condition_matches(&self.delta_nonstaked_amount, &event.delta_nonstaked_amount)
+            && condition_matches(
+                &self.absolute_nonstaked_amount,
+                &event.absolute_nonstaked_amount,
+            )
+            && condition_matches(&self.delta_staked_amount, &event.delta_staked_amount)
+            && condition_matches(&self.absolute_staked_amount, &event.absolute_staked_amount)
+    }
+}
+
+fn load_rules(path: &std::path::Path) -> anyhow::Result<Vec<Rule>> {
+    serde_json::from_str(&std::fs::read_to_string(path)?)
+        .map_err(|err| anyhow::anyhow!("Failed to parse {}: {}", path.display(), err))
+}
+
+/// Evaluates the alert rules against every event
+pub(crate) struct AlertsSink {
+    rules: Vec<Rule>,
+    webhooks: Option<DeliveryQueue>,
+    pool: sqlx::PgPool,
+    /// The events up to it have already fired their alerts
+    last_alerted_event_index: tokio::sync::Mutex<Option<BigDecimal>>,
+}
+
+impl AlertsSink {
+    pub(crate) async fn connect(
+        rules_path: &std::path::Path,
+        webhooks_config: &crate::configs::WebhooksConfig,
+    ) -> anyhow::Result<Self> {
+        let rules = load_rules(rules_path)?;
+        let outputs = || rules.iter().flat_map(|rule| &rule.outputs);
+        let needs_webhooks = outputs().any(|output| matches!(output, Output::Webhook(_)));
+
+        // The table keeps the alerts, the dead letters of the webhooks and the cursor
+        let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+        let last_alerted_event_index = load_last_alerted_event_index(&pool).await?;
+        Ok(Self {
+            webhooks: if needs_webhooks {
+                Some(DeliveryQueue::new(webhooks_config, Some(pool.clone()))?)
+            } else {
+                None
+            },
+            pool,
+            last_alerted_event_index: tokio::sync::Mutex::new(last_alerted_event_index),
+            rules,
+        })
+    }
+}
+
+async fn load_last_alerted_event_index(pool: &sqlx::PgPool) -> anyhow::Result<Option<BigDecimal>> {
+    let rows = crate::models::select_retry_or_panic(
+        pool,
+        "SELECT CAST(event_index AS TEXT) FROM near_balance_alerts_cursor",
+        &[],
+        crate::RETRY_COUNT,
+    )
+    .await?;
+    rows.first()
+        .map(|row| {
+            Ok(BigDecimal::from_str(&sqlx::Row::try_get::<String, _>(
+                row, 0,
+            )?)?)
+        })
+        .transpose()
+}
+
+async fn save_last_alerted_event_index(
+    pool: &sqlx::PgPool,
+    event_index: &BigDecimal,
+) -> anyhow::Result<()> {
+    // Never moves back, e.g. when the indexer is started from the earlier block
+    crate::models::execute_retry_or_panic(
+        pool,
+        "INSERT INTO near_balance_alerts_cursor (event_index) VALUES ($1::numeric)
+        ON CONFLICT (id) DO UPDATE
+            SET event_index = GREATEST(near_balance_alerts_cursor.event_index, EXCLUDED.event_index)",
+        &[event_index.to_string()],
+        crate::RETRY_COUNT,
+    )
+    .await
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for AlertsSink {
+    fn name(&self) -> &'static str {
+        "alerts"
+    }
+
+    async fn store_block(
+        &self,
+        _block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        let mut last_alerted_event_index = self.last_alerted_event_index.lock().await;
+        let mut alerts = vec![];
+        let mut fired_event_index = None;
+        for event in events {
+            // The block is replayed after a restart
+            if matches!(&*last_alerted_event_index, Some(index) if &event.event_index <= index) {
+                continue;
+            }
+            for rule in self.rules.iter().filter(|rule| rule.matches(event)) {
+                fired_event_index = Some(event.event_index.clone());
+                crate::metrics::ALERTS_TOTAL
+                    .with_label_values(&[&rule.name])
+                    .inc();
+                for output in &rule.outputs {
+                    match output {
+                        Output::Log => tracing::warn!(
+                            target: crate::LOGGING_PREFIX,
+                            "Alert {}: {} at block {}, nonstaked delta {}, staked delta {}, event {}",
+                            rule.name,
+                            event.affected_account_id,
+                            event.block_height,
+                            event.delta_nonstaked_amount,
+                            event.delta_staked_amount,
+                            event.event_index,
+                        ),
+                        Output::Table => alerts.push(NearBalanceAlert {
+                            rule_name: rule.name.clone(),
+                            event_index: event.event_index.clone(),
+                            block_height: event.block_height.clone(),
+                            affected_account_id: event.affected_account_id.clone(),
+                            delta_nonstaked_amount: event.delta_nonstaked_amount.clone(),
+                            delta_staked_amount: event.delta_staked_amount.clone(),
+                        }),
+                        Output::Webhook(webhook) => {
+                            let payload = serde_json::json!({
+                                "rule": rule.name,
+                                "event": EventMessage::from(event),
+                            });
+                            self.webhooks
+                                .as_ref()
+                                .expect("Webhooks are set up when the rules have them")
+                                .push(webhook, event, payload.to_string())
+                                .await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        crate::models::chunked_insert(&self.pool, &alerts, crate::RETRY_COUNT).await?;
+        if let Some(event_index) = fired_event_index {
+            save_last_alerted_event_index(&self.pool, &event_index).await?;
+            *last_alerted_event_index = Some(event_index);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::EventSink;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    const NEAR: i128 = 10i128.pow(24);
+
+    fn yocto(amount: i128) -> BigDecimal {
+        BigDecimal::from_str(&amount.to_string()).unwrap()
+    }
+
+    fn rules() -> Vec<Rule> {
+        serde_json::from_value(serde_json::json!([
+            {
+                "name": "large_transfer",
+                "delta_nonstaked_amount": {"abs": true, "gt": "100000 NEAR"},
+                "outputs": [{"type": "log"}]
+            },
+            {
+                "name": "hot_wallet_outbound",
+                "accounts": ["hot-wallet.near"],
+                "direction": "OUTBOUND",
+                "outputs": [{"type": "table"}]
+            },
+            {
+                "name": "stake_withdrawn",
+                "delta_staked_amount": {"lt": "0"},
+                "absolute_staked_amount": {"eq": "0"},
+                "outputs": [{"type": "webhook", "url": "http://localhost/hook", "secret": "secret"}]
+            }
+        ]))
+        .unwrap()
+    }
+
+    fn matched_rules(event: &NearBalanceEvent) -> Vec<String> {
+        rules()
+            .into_iter()
+            .filter(|rule| rule.matches(event))
+            .map(|rule| rule.name)
+            .collect()
+    }
+
+    #[test]
+    fn parses_amounts_in_near() {
+        let amount = |value: &str| Amount::try_from(value.to_string()).map(|amount| amount.0);
+        assert_eq!(amount("100000 NEAR").unwrap(), yocto(100_000 * NEAR));
+        assert_eq!(amount("0.5 NEAR").unwrap(), yocto(NEAR / 2));
+        assert_eq!(amount("12345").unwrap(), BigDecimal::from(12345));
+        assert!(amount("a lot").is_err());
+    }
+
+    #[test]
+    fn matches_rules() {
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let event = |account: &str, delta: i128| balance_event(&header, 0, account, delta, 0);
+
+        assert_eq!(
+            matched_rules(&event("alice.near", -200_000 * NEAR)),
+            vec!["large_transfer"]
+        );
+        assert!(matched_rules(&event("alice.near", 100_000 * NEAR)).is_empty());
+        assert_eq!(
+            matched_rules(&event("hot-wallet.near", -1)),
+            vec!["hot_wallet_outbound"]
+        );
+        assert!(matched_rules(&event("hot-wallet.near", 1)).is_empty());
+
+        let mut unstaked = event("validator.near", 0);
+        unstaked.delta_staked_amount = yocto(-NEAR);
+        assert_eq!(matched_rules(&unstaked), vec!["stake_withdrawn"]);
+        unstaked.absolute_staked_amount = yocto(NEAR);
+        assert!(matched_rules(&unstaked).is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let rules: Result<Vec<Rule>, _> = serde_json::from_value(serde_json::json!([
+            {"name": "typo", "delta_nonstaked": {"gt": "1"}, "outputs": []}
+        ]));
+        assert!(rules.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn stores_alerts_to_table() {
+        let (pool, _schema) = test_pool().await;
+        let sink = AlertsSink {
+            rules: rules(),
+            webhooks: None,
+            pool: pool.clone(),
+            last_alerted_event_index: tokio::sync::Mutex::new(None),
+        };
+        let header = block_header(1, 1_000_000_000);
+        let events = vec![
+            balance_event(&header, 0, "hot-wallet.near", -5, 10),
+            balance_event(&header, 1, "hot-wallet.near", 5, 15),
+        ];
+        let fired = || {
+            crate::metrics::ALERTS_TOTAL
+                .with_label_values(&["hot_wallet_outbound"])
+                .get()
+        };
+        let fired_before = fired();
+        sink.store_block(&header, &events).await.unwrap();
+        // Replayed blocks do not fire the alerts again
+        sink.store_block(&header, &events).await.unwrap();
+        assert_eq!(fired(), fired_before + 1);
+        assert_eq!(
+            load_last_alerted_event_index(&pool).await.unwrap(),
+            Some(events[0].event_index.clone())
+        );
+
+        let alerts: Vec<NearBalanceAlert> = sqlx::query_as(
+            "SELECT rule_name, event_index, block_height, affected_account_id, delta_nonstaked_amount, delta_staked_amount
+             FROM near_balance_alerts",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            alerts,
+            vec![NearBalanceAlert {
+                rule_name: "hot_wallet_outbound".to_string(),
+                event_index: events[0].event_index.clone(),
+                block_height: 1.into(),
+                affected_account_id: "hot-wallet.near".to_string(),
+                delta_nonstaked_amount: BigDecimal::from(-5),
+                delta_staked_amount: 0.into(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+
+use crate::models::balance_changes::NearBalanceEvent;
+
+// ReplacingMergeTree drops the duplicates we write after the restart
+const CREATE_TABLE_QUERY: &str = "
+CREATE TABLE IF NOT EXISTS near_balance_events
+(
+    event_index               Decimal(38, 0),
+    block_timestamp           UInt64,
+    block_height              UInt64,
+    receipt_id                Nullable(String),
+    transaction_hash          Nullable(String),
+    affected_account_id       String,
+    involved_account_id       Nullable(String),
+    direction                 LowCardinality(String),
+    cause                     LowCardinality(String),
+    status                    LowCardinality(String),
+    delta_nonstaked_amount    Decimal(40, 0),
+    absolute_nonstaked_amount Decimal(40, 0),
+    delta_staked_amount       Decimal(40, 0),
+    absolute_staked_amount    Decimal(40, 0)
+)
+ENGINE = ReplacingMergeTree
+ORDER BY (affected_account_id, event_index)";
+
+// TSV keeps the big decimals as they are, JSON would need them as numbers
+const INSERT_QUERY: &str = "INSERT INTO near_balance_events FORMAT TabSeparated";
+
+#[derive(Default)]
+struct Buffer {
+    events: Vec<NearBalanceEvent>,
+    first_event_at: Option<std::time::Instant>,
+}
+
+/// Writes the events to ClickHouse through the HTTP interface.
+/// ClickHouse prefers big inserts, so the events are buffered across the blocks
+/// and flushed by size or by time. The buffered events are lost on the restart,
+/// we resume from the latest block stored in ClickHouse, so they are indexed again
+pub(crate) struct ClickHouseSink {
+    client: reqwest::Client,
+    config: crate::configs::ClickHouseSinkConfig,
+    buffer: tokio::sync::Mutex<Buffer>,
+}
+
+impl ClickHouseSink {
+    pub(crate) async fn connect(
+        config: &crate::configs::ClickHouseSinkConfig,
+    ) -> anyhow::Result<Self> {
+        let sink = Self {
+            client: reqwest::Client::new(),
+            config: config.clone(),
+            buffer: Default::default(),
+        };
+        sink.execute_retriable(CREATE_TABLE_QUERY, String::new())
+            .await?;
+        Ok(sink)
+    }
+
+    async fn execute(&self, query: &str, body: String) -> anyhow::Result<String> {
+        let mut request = self
+            .client
+            .post(&self.config.clickhouse_url)
+            .query(&[
+                ("database", self.config.clickhouse_database.as_str()),
+                ("query", query),
+            ])
+            .body(body);
+        if let Some(user) = &self.config.clickhouse_user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.config.clickhouse_password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!("ClickHouse responded with {}: {}", status, text.trim());
+        }
+        Ok(text)
+    }
+
+    async fn execute_retriable(&self, query: &str, body: String) -> anyhow::Result<String> {
+        let mut interval = crate::INTERVAL;
+        let mut retry_attempt = 0usize;
+
+        loop {
+            if retry_attempt == crate::RETRY_COUNT {
+                anyhow::bail!(
+                    "Failed to perform query to ClickHouse after {} attempts. Stop trying.\n{}",
+                    crate::RETRY_COUNT,
+                    query
+                );
+            }
+            retry_attempt += 1;
+
+            match self.execute(query, body.clone()).await {
+                Ok(res) => return Ok(res),
+                Err(err) => {
+                    tracing::error!(
+                        target: crate::LOGGING_PREFIX,
+                        "Error occurred during ClickHouse query {}:\n{}\n Retrying in {} milliseconds...",
+                        query,
+                        err,
+                        interval.as_millis(),
+                    );
+                    tokio::time::sleep(interval).await;
+                    if interval < crate::MAX_DELAY_TIME {
+                        interval *= 2;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn insert(&self, events: &[NearBalanceEvent]) -> anyhow::Result<()> {
+        let body: String = events.iter().map(to_tsv_row).collect();
+        self.execute_retriable(INSERT_QUERY, body).await?;
+        Ok(())
+    }
+}
+
+fn to_tsv_row(event: &NearBalanceEvent) -> String {
+    let optional = |value: &Option<String>| value.as_deref().map_or("\\N".to_string(), escape);
+    let fields = [
+        event.event_index.to_string(),
+        event.block_timestamp.to_string(),
+        event.block_height.to_string(),
+        optional(&event.receipt_id),
+        optional(&event.transaction_hash),
+        escape(&event.affected_account_id),
+        optional(&event.involved_account_id),
+        escape(&event.direction),
+        escape(&event.cause),
+        escape(&event.status),
+        event.delta_nonstaked_amount.to_string(),
+        event.absolute_nonstaked_amount.to_string(),
+        event.delta_staked_amount.to_string(),
+        event.absolute_staked_amount.to_string(),
+    ];
+    fields.join("\t") + "\n"
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for ClickHouseSink {
+    fn name(&self) -> &'static str {
+        "clickhouse"
+    }
+
+    async fn store_block(
+        &self,
+        _block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        let mut buffer = self.buffer.lock().await;
+        if !events.is_empty() {
+            buffer.events.extend_from_slice(events);
+            buffer
+                .first_event_at
+                .get_or_insert_with(std::time::Instant::now);
+        }
+
+        let is_full = buffer.events.len() >= self.config.clickhouse_batch_size;
+        let is_stale = buffer.first_event_at.map_or(false, |first_event_at| {
+            first_event_at.elapsed()
+                >= std::time::Duration::from_secs(self.config.clickhouse_flush_interval_secs)
+        });
+        if is_full || is_stale {
+            self.insert(&buffer.events).await?;
+            *buffer = Default::default();
+        }
+        Ok(())
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        let response = self
+            .execute_retriable(
+                "SELECT max(block_height) FROM near_balance_events FORMAT TabSeparated",
+                String::new(),
+            )
+            .await?;
+        // max() of the empty table is 0 in ClickHouse
+        match response.trim().parse::<u64>()? {
+            0 => Ok(None),
+            height => Ok(Some(
+                height.saturating_sub(crate::models::RESTART_OVERLAP_BLOCKS),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::*;
+    use crate::sinks::EventSink;
+    use crate::test_utils::{balance_event, block_header};
+
+    type Requests = std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>;
+
+    async fn mock_clickhouse(
+        requests: web::Data<Requests>,
+        query: web::Query<std::collections::HashMap<String, String>>,
+        body: String,
+    ) -> HttpResponse {
+        let query = query.get("query").cloned().unwrap_or_default();
+        requests.lock().unwrap().push((query.clone(), body));
+        if query.starts_with("SELECT max(block_height)") {
+            HttpResponse::Ok().body("70000000\n")
+        } else {
+            HttpResponse::Ok().finish()
+        }
+    }
+
+    fn config(url: String) -> crate::configs::ClickHouseSinkConfig {
+        crate::configs::ClickHouseSinkConfig {
+            clickhouse_url: url,
+            clickhouse_database: "default".to_string(),
+            clickhouse_user: None,
+            clickhouse_password: None,
+            clickhouse_batch_size: 3,
+            clickhouse_flush_interval_secs: 3600,
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_events_across_blocks() {
+        let requests: Requests = Default::default();
+        let data = web::Data::new(requests.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/", web::post().to(mock_clickhouse))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/", server.addrs()[0]);
+        tokio::spawn(server.run());
+
+        let sink = ClickHouseSink::connect(&config(url)).await.unwrap();
+        let first = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let second = block_header(70_000_001, 1_657_000_001_000_000_000);
+        sink.store_block(&first, &[balance_event(&first, 0, "alice.near", -5, 10)])
+            .await
+            .unwrap();
+        sink.store_block(
+            &second,
+            &[
+                balance_event(&second, 0, "alice.near", 1, 11),
+                balance_event(&second, 1, "bob.near", -1, 0),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            sink.start_after_interruption().await.unwrap(),
+            Some(70_000_000 - 1000)
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3, "{:#?}", requests);
+        assert!(requests[0]
+            .0
+            .contains("CREATE TABLE IF NOT EXISTS near_balance_events"));
+        assert_eq!(requests[1].0, INSERT_QUERY);
+        let rows: Vec<Vec<&str>> = requests[1]
+            .1
+            .lines()
+            .map(|line| line.split('\t').collect())
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2][2], "70000001");
+        assert_eq!(rows[2][5], "bob.near");
+        assert_eq!(rows[2][6], "\\N");
+        assert_eq!(rows[2][10], "-1");
+    }
+
+    // Run with `CLICKHOUSE_URL=http://localhost:8123 cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn writes_to_local_clickhouse() {
+        let url = std::env::var("CLICKHOUSE_URL").expect("CLICKHOUSE_URL is required");
+        let sink = ClickHouseSink::connect(&config(url)).await.unwrap();
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let events: Vec<NearBalanceEvent> = (0..3)
+            .map(|i| {
+                balance_event(
+                    &header,
+                    i,
+                    "alice.near",
+                    -99_999_999_999_999_999_999_999_999_999_999_999,
+                    i,
+                )
+            })
+            .collect();
+        sink.store_block(&header, &events).await.unwrap();
+
+        let max_delta = sink
+            .execute_retriable(
+                "SELECT toString(min(delta_nonstaked_amount)) FROM near_balance_events FORMAT TabSeparated",
+                String::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(max_delta.trim(), "-99999999999999999999999999999999999999");
+        assert!(sink.start_after_interruption().await.unwrap().is_some());
+    }
+}
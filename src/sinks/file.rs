@@ -0,0 +1,698 @@
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+
+use crate::models::balance_changes::NearBalanceEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileFormat {
+    JsonLines,
+    Csv,
+}
+
+impl FileFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::JsonLines => "jsonl",
+            FileFormat::Csv => "csv",
+        }
+    }
+}
+
+enum EventWriter {
+    JsonLines(Box<dyn Write + Send>),
+    Csv(Box<csv::Writer<Box<dyn Write + Send>>>),
+}
+
+impl EventWriter {
+    fn write(&mut self, event: &NearBalanceEvent) -> anyhow::Result<()> {
+        match self {
+            EventWriter::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, event)?;
+                writer.write_all(b"\n")?;
+            }
+            EventWriter::Csv(writer) => writer.serialize(event)?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        match self {
+            EventWriter::JsonLines(writer) => writer.flush()?,
+            EventWriter::Csv(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+// Keeps track of the file size on disk, we can't ask the file itself while it's wrapped into the encoders
+struct CountingFile {
+    file: std::fs::File,
+    bytes_written: Arc<AtomicU64>,
+}
+
+impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.bytes_written
+            .fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+struct OpenFile {
+    writer: EventWriter,
+    first_block_height: u64,
+    bytes_written: Arc<AtomicU64>,
+}
+
+const FILE_NAME_PREFIX: &str = "near_balance_events_";
+
+/// The part of the written event we need after the restart
+#[derive(serde::Deserialize)]
+struct WrittenEvent {
+    block_height: bigdecimal::BigDecimal,
+}
+
+/// What the restart needs to know about the file written by the previous run
+struct WrittenFile {
+    /// Where the last block starts in the uncompressed content, or where the events start if there are none
+    last_block_offset: u64,
+    last_block_height: Option<u64>,
+    /// The block before the last one, the last block known to be complete
+    previous_block_height: Option<u64>,
+}
+
+/// Writes the events to the local files, one event per line.
+/// Files are named by the first block they contain and rotated by block range and/or size
+pub(crate) struct FileSink {
+    format: FileFormat,
+    config: crate::configs::FileSinkConfig,
+    file: std::sync::Mutex<Option<OpenFile>>,
+    /// The last complete block in the files of the previous runs
+    last_written_block_height: Option<u64>,
+}
+
+impl FileSink {
+    pub(crate) fn new(
+        format: FileFormat,
+        config: &crate::configs::FileSinkConfig,
+    ) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&config.file_sink_dir)?;
+        let mut sink = Self {
+            format,
+            config: config.clone(),
+            file: std::sync::Mutex::new(None),
+            last_written_block_height: None,
+        };
+        sink.last_written_block_height = sink.recover_written_files()?;
+        Ok(sink)
+    }
+
+    // The files are named by their first block, so only the latest one may end with the unfinished block.
+    // The crash may leave there the broken line, or only the part of the block events,
+    // so we cut the file at the beginning of its last block and write that block again
+    fn recover_written_files(&self) -> anyhow::Result<Option<u64>> {
+        let suffix = self.file_name_suffix();
+        let mut files: Vec<(u64, PathBuf)> = vec![];
+        for entry in std::fs::read_dir(&self.config.file_sink_dir)? {
+            let path = entry?.path();
+            let first_block_height = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(FILE_NAME_PREFIX))
+                .and_then(|name| name.strip_suffix(&suffix))
+                .and_then(|height| height.parse::<u64>().ok());
+            if let Some(first_block_height) = first_block_height {
+                files.push((first_block_height, path));
+            }
+        }
+        files.sort();
+
+        let (_, latest_path) = match files.pop() {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        let latest = self.read_written_file(&latest_path)?;
+        if let Some(block_height) = latest.last_block_height {
+            tracing::info!(
+                target: crate::LOGGING_PREFIX,
+                "Block {} may be unfinished in {}, it will be written again",
+                block_height,
+                latest_path.display()
+            );
+        }
+        self.cut_file(&latest_path, latest.last_block_offset)?;
+        if latest.previous_block_height.is_some() {
+            return Ok(latest.previous_block_height);
+        }
+
+        // The older files were finished before the next one was opened
+        for (_, path) in files.iter().rev() {
+            if let Some(height) = self.read_written_file(path)?.last_block_height {
+                return Ok(Some(height));
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_written_file(&self, path: &Path) -> anyhow::Result<WrittenFile> {
+        let file = std::fs::File::open(path)?;
+        let input: Box<dyn std::io::Read> = if self.config.file_gzip {
+            Box::new(flate2::read::MultiGzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut input = std::io::BufReader::new(input);
+        let mut written = WrittenFile {
+            last_block_offset: 0,
+            last_block_height: None,
+            previous_block_height: None,
+        };
+        let mut offset = 0;
+        let mut line = vec![];
+
+        let csv_column = match self.format {
+            FileFormat::JsonLines => None,
+            FileFormat::Csv => {
+                match read_line(&mut input, &mut line).and_then(|()| csv_column(&line)) {
+                    Ok(column) => Some(column),
+                    Err(err) => {
+                        tracing::warn!(
+                            target: crate::LOGGING_PREFIX,
+                            "{} has no valid header: {}",
+                            path.display(),
+                            err
+                        );
+                        return Ok(written);
+                    }
+                }
+            }
+        };
+        offset += line.len() as u64;
+        written.last_block_offset = offset;
+
+        loop {
+            let block_height = match read_line(&mut input, &mut line) {
+                Ok(()) if line.is_empty() => break,
+                Ok(()) => match csv_column {
+                    None => parse_json_block_height(&line),
+                    Some(column) => parse_csv_block_height(&line, column),
+                },
+                Err(err) => Err(err),
+            };
+            let block_height = match block_height {
+                Ok(block_height) => block_height,
+                Err(err) => {
+                    // The crash may leave the last line unfinished
+                    tracing::warn!(
+                        target: crate::LOGGING_PREFIX,
+                        "Stopped reading {} at the broken line: {}",
+                        path.display(),
+                        err
+                    );
+                    break;
+                }
+            };
+            if written.last_block_height != Some(block_height) {
+                written.previous_block_height = written.last_block_height;
+                written.last_block_height = Some(block_height);
+                written.last_block_offset = offset;
+            }
+            offset += line.len() as u64;
+        }
+        Ok(written)
+    }
+
+    /// Drops everything after `offset` of the uncompressed content, the file is removed if nothing is left
+    fn cut_file(&self, path: &Path, offset: u64) -> anyhow::Result<()> {
+        if offset == 0 {
+            std::fs::remove_file(path)?;
+            return Ok(());
+        }
+        if self.config.file_gzip {
+            // The compressed stream can't be cut in the middle, so the kept part is compressed again
+            let tmp_path = path.with_extension("tmp");
+            let mut input = std::io::Read::take(
+                flate2::read::MultiGzDecoder::new(std::fs::File::open(path)?),
+                offset,
+            );
+            let mut output = flate2::write::GzEncoder::new(
+                std::fs::File::create(&tmp_path)?,
+                flate2::Compression::default(),
+            );
+            std::io::copy(&mut input, &mut output)?;
+            output.finish()?.sync_all()?;
+            std::fs::rename(&tmp_path, path)?;
+        } else {
+            let file = std::fs::OpenOptions::new().write(true).open(path)?;
+            file.set_len(offset)?;
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self, file: &OpenFile, block_height: u64) -> bool {
+        if let Some(blocks) = self.config.file_rotate_blocks {
+            if block_height / blocks != file.first_block_height / blocks {
+                return true;
+            }
+        }
+        if let Some(bytes) = self.config.file_rotate_bytes {
+            if file.bytes_written.load(Ordering::Relaxed) >= bytes {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn file_name_suffix(&self) -> String {
+        let mut suffix = format!(".{}", self.format.extension());
+        if self.config.file_gzip {
+            suffix += ".gz";
+        }
+        suffix
+    }
+
+    fn path(&self, first_block_height: u64) -> PathBuf {
+        self.config.file_sink_dir.join(format!(
+            "{}{:012}{}",
+            FILE_NAME_PREFIX,
+            first_block_height,
+            self.file_name_suffix()
+        ))
+    }
+
+    fn open(&self, block_height: u64) -> anyhow::Result<OpenFile> {
+        // With block range rotation, the file name is the beginning of the range,
+        // so we continue writing to the same file after the restart
+        let first_block_height = match self.config.file_rotate_blocks {
+            Some(blocks) => block_height - block_height % blocks,
+            None => block_height,
+        };
+        let path = self.path(first_block_height);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let file_size = file.metadata()?.len();
+        let bytes_written = Arc::new(AtomicU64::new(file_size));
+        let file = CountingFile {
+            file,
+            bytes_written: bytes_written.clone(),
+        };
+        tracing::info!(
+            target: crate::LOGGING_PREFIX,
+            "Writing balance events to {}",
+            path.display()
+        );
+
+        let output: Box<dyn Write + Send> = if self.config.file_gzip {
+            // Appending to the existing gzip file adds a new gzip member, the result is still a valid gzip
+            Box::new(std::io::BufWriter::new(flate2::write::GzEncoder::new(
+                file,
+                flate2::Compression::default(),
+            )))
+        } else {
+            Box::new(std::io::BufWriter::new(file))
+        };
+        let writer = match self.format {
+            FileFormat::JsonLines => EventWriter::JsonLines(output),
+            FileFormat::Csv => EventWriter::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .has_headers(file_size == 0)
+                    .from_writer(output),
+            )),
+        };
+
+        Ok(OpenFile {
+            writer,
+            first_block_height,
+            bytes_written,
+        })
+    }
+
+    fn write_block(&self, block_height: u64, events: &[NearBalanceEvent]) -> anyhow::Result<()> {
+        let mut file_lock = self
+            .file
+            .lock()
+            .map_err(|_| anyhow::anyhow!("File sink lock is poisoned"))?;
+
+        if let Some(file) = file_lock.as_ref() {
+            if self.should_rotate(file, block_height) {
+                // Dropping the writer flushes it and finishes the gzip stream
+                *file_lock = None;
+            }
+        }
+        if events.is_empty() {
+            return Ok(());
+        }
+        if file_lock.is_none() {
+            *file_lock = Some(self.open(block_height)?);
+        }
+
+        let file = file_lock.as_mut().expect("File was opened above");
+        for event in events {
+            file.writer.write(event)?;
+        }
+        // The block is considered stored only when it is written to the file
+        file.writer.flush()
+    }
+}
+
+/// Reads the next line including its `\n`, the line without it is the unfinished one
+fn read_line(input: &mut impl BufRead, line: &mut Vec<u8>) -> anyhow::Result<()> {
+    line.clear();
+    input.read_until(b'\n', line)?;
+    if !line.is_empty() && !line.ends_with(b"\n") {
+        anyhow::bail!("The line has no end");
+    }
+    Ok(())
+}
+
+fn csv_column(header: &[u8]) -> anyhow::Result<usize> {
+    csv::Reader::from_reader(header)
+        .headers()?
+        .iter()
+        .position(|header| header == "block_height")
+        .ok_or_else(|| anyhow::anyhow!("The header has no block_height"))
+}
+
+fn parse_json_block_height(line: &[u8]) -> anyhow::Result<u64> {
+    Ok(serde_json::from_slice::<WrittenEvent>(line)?
+        .block_height
+        .to_string()
+        .parse()?)
+}
+
+fn parse_csv_block_height(line: &[u8], column: usize) -> anyhow::Result<u64> {
+    let record = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(line)
+        .records()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("The line is empty"))??;
+    Ok(record
+        .get(column)
+        .ok_or_else(|| anyhow::anyhow!("The line has no block_height"))?
+        .parse()?)
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for FileSink {
+    fn name(&self) -> &'static str {
+        match self.format {
+            FileFormat::JsonLines => "jsonl",
+            FileFormat::Csv => "csv",
+        }
+    }
+
+    async fn store_block(
+        &self,
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        // These blocks are already in the files, other sinks may still ask to start from them
+        if matches!(self.last_written_block_height, Some(height) if block_header.height <= height) {
+            return Ok(());
+        }
+        self.write_block(block_header.height, events)
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        Ok(self.last_written_block_height.map(|height| height + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn event(block_height: u64, affected_account_id: &str) -> NearBalanceEvent {
+        NearBalanceEvent {
+            event_index: block_height.into(),
+            block_timestamp: 1657000000000000000u64.into(),
+            block_height: block_height.into(),
+            receipt_id: None,
+            transaction_hash: Some("FGSPpucGQBUTPscfjQRs7Poo4XyaXGawX6QriKbhT3sE".to_string()),
+            affected_account_id: affected_account_id.to_string(),
+            involved_account_id: None,
+            direction: "OUTBOUND".to_string(),
+            cause: "TRANSACTION".to_string(),
+            status: "SUCCESS".to_string(),
+            delta_nonstaked_amount: (-1000).into(),
+            absolute_nonstaked_amount: 100_000_000_000_000_000_000_000_000u128
+                .to_string()
+                .parse()
+                .unwrap(),
+            delta_staked_amount: 0.into(),
+            absolute_staked_amount: 0.into(),
+            cause_payload: None,
+        }
+    }
+
+    fn config(dir: &tempfile::TempDir) -> crate::configs::FileSinkConfig {
+        crate::configs::FileSinkConfig {
+            file_sink_dir: dir.path().to_path_buf(),
+            file_rotate_blocks: None,
+            file_rotate_bytes: None,
+            file_gzip: false,
+        }
+    }
+
+    fn file_names(dir: &tempfile::TempDir) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotates_by_block_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FileSink::new(
+            FileFormat::JsonLines,
+            &crate::configs::FileSinkConfig {
+                file_rotate_blocks: Some(10),
+                ..config(&dir)
+            },
+        )
+        .unwrap();
+
+        sink.write_block(5, &[event(5, "alice.near")]).unwrap();
+        sink.write_block(9, &[event(9, "bob.near")]).unwrap();
+        sink.write_block(14, &[event(14, "carol.near")]).unwrap();
+        // Blocks without events do not create empty files
+        sink.write_block(25, &[]).unwrap();
+        drop(sink);
+
+        assert_eq!(
+            file_names(&dir),
+            vec![
+                "near_balance_events_000000000000.jsonl",
+                "near_balance_events_000000000010.jsonl"
+            ]
+        );
+        let content =
+            std::fs::read_to_string(dir.path().join("near_balance_events_000000000000.jsonl"))
+                .unwrap();
+        let events: Vec<NearBalanceEvent> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events, vec![event(5, "alice.near"), event(9, "bob.near")]);
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FileSink::new(
+            FileFormat::Csv,
+            &crate::configs::FileSinkConfig {
+                file_rotate_bytes: Some(1),
+                ..config(&dir)
+            },
+        )
+        .unwrap();
+
+        sink.write_block(7, &[event(7, "alice.near"), event(7, "bob.near")])
+            .unwrap();
+        sink.write_block(8, &[event(8, "carol.near")]).unwrap();
+        drop(sink);
+
+        assert_eq!(
+            file_names(&dir),
+            vec![
+                "near_balance_events_000000000007.csv",
+                "near_balance_events_000000000008.csv"
+            ]
+        );
+    }
+
+    fn read_gzipped(path: &Path) -> String {
+        let mut content = String::new();
+        flate2::read::MultiGzDecoder::new(std::fs::File::open(path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn appends_to_gzipped_csv_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = crate::configs::FileSinkConfig {
+            file_rotate_blocks: Some(1000),
+            file_gzip: true,
+            ..config(&dir)
+        };
+
+        let sink = FileSink::new(FileFormat::Csv, &config).unwrap();
+        sink.write_block(1, &[event(1, "alice.near")]).unwrap();
+        sink.write_block(2, &[event(2, "bob.near")]).unwrap();
+        drop(sink);
+        let sink = FileSink::new(FileFormat::Csv, &config).unwrap();
+        // The last block may be unfinished, it is written again
+        assert_eq!(sink.last_written_block_height, Some(1));
+        sink.write_block(2, &[event(2, "bob.near")]).unwrap();
+        sink.write_block(3, &[event(3, "carol.near")]).unwrap();
+        drop(sink);
+
+        let content = read_gzipped(&dir.path().join("near_balance_events_000000000000.csv.gz"));
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4, "The header should be written only once");
+        assert!(lines[0].starts_with("event_index,block_timestamp,block_height,"));
+        assert!(lines[1].contains(",alice.near,"));
+        assert!(lines[2].contains(",bob.near,"));
+        assert!(lines[3].contains(",carol.near,"));
+    }
+
+    async fn store_block(sink: &FileSink, block_height: u64, affected_account_id: &str) {
+        crate::sinks::EventSink::store_block(
+            sink,
+            &crate::test_utils::block_header(block_height, 1657000000000000000),
+            &[event(block_height, affected_account_id)],
+        )
+        .await
+        .unwrap();
+    }
+
+    fn read_jsonl_events(dir: &tempfile::TempDir) -> Vec<NearBalanceEvent> {
+        file_names(dir)
+            .iter()
+            .flat_map(|name| {
+                std::fs::read_to_string(dir.path().join(name))
+                    .unwrap()
+                    .lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn skips_written_blocks_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FileSink::new(FileFormat::JsonLines, &config(&dir)).unwrap();
+        store_block(&sink, 5, "alice.near").await;
+        store_block(&sink, 9, "bob.near").await;
+        drop(sink);
+
+        let sink = FileSink::new(FileFormat::JsonLines, &config(&dir)).unwrap();
+        // The last block may be unfinished, it is written again
+        assert_eq!(
+            crate::sinks::EventSink::start_after_interruption(&sink)
+                .await
+                .unwrap(),
+            Some(6)
+        );
+        store_block(&sink, 5, "alice.near").await;
+        store_block(&sink, 9, "bob.near").await;
+        store_block(&sink, 10, "carol.near").await;
+        drop(sink);
+
+        assert_eq!(
+            read_jsonl_events(&dir),
+            vec![
+                event(5, "alice.near"),
+                event(9, "bob.near"),
+                event(10, "carol.near")
+            ],
+            "The blocks should not be written twice"
+        );
+    }
+
+    #[test]
+    fn cuts_unfinished_block_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = crate::configs::FileSinkConfig {
+            file_rotate_blocks: Some(1000),
+            ..config(&dir)
+        };
+        let path = dir.path().join("near_balance_events_000000000000.jsonl");
+        let sink = FileSink::new(FileFormat::JsonLines, &config).unwrap();
+        sink.write_block(1, &[event(1, "alice.near")]).unwrap();
+        sink.write_block(2, &[event(2, "bob.near"), event(2, "carol.near")])
+            .unwrap();
+        drop(sink);
+        // The crash in the middle of the line of block 2
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 10).unwrap();
+
+        let sink = FileSink::new(FileFormat::JsonLines, &config).unwrap();
+        assert_eq!(sink.last_written_block_height, Some(1));
+        sink.write_block(2, &[event(2, "bob.near"), event(2, "carol.near")])
+            .unwrap();
+        sink.write_block(3, &[event(3, "dave.near")]).unwrap();
+        drop(sink);
+
+        assert_eq!(
+            read_jsonl_events(&dir),
+            vec![
+                event(1, "alice.near"),
+                event(2, "bob.near"),
+                event(2, "carol.near"),
+                event(3, "dave.near")
+            ]
+        );
+    }
+
+    #[test]
+    fn replaces_broken_gzip_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = crate::configs::FileSinkConfig {
+            file_rotate_blocks: Some(1000),
+            file_gzip: true,
+            ..config(&dir)
+        };
+        let path = dir.path().join("near_balance_events_000000000000.jsonl.gz");
+        let sink = FileSink::new(FileFormat::JsonLines, &config).unwrap();
+        sink.write_block(1, &[event(1, "alice.near")]).unwrap();
+        sink.write_block(2, &[event(2, "bob.near")]).unwrap();
+        drop(sink);
+        // The crash before the gzip stream is finished
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 4).unwrap();
+
+        let sink = FileSink::new(FileFormat::JsonLines, &config).unwrap();
+        assert_eq!(sink.last_written_block_height, Some(1));
+        sink.write_block(2, &[event(2, "bob.near")]).unwrap();
+        drop(sink);
+
+        let events: Vec<NearBalanceEvent> = read_gzipped(&path)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events, vec![event(1, "alice.near"), event(2, "bob.near")]);
+    }
+}
@@ -0,0 +1,125 @@
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::sinks::EventMessage;
+
+/// Publishes the events to a Kafka topic, keyed by `affected_account_id`,
+/// so all the events of the account land in one partition in the order they happened.
+/// The block is reported as stored only when the broker has acknowledged all its events,
+/// so the messages are delivered at least once: after the restart we publish them again
+pub(crate) struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaSink {
+    pub(crate) fn new(config: &crate::configs::KafkaSinkConfig) -> anyhow::Result<Self> {
+        let producer = rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", &config.kafka_brokers)
+            // No duplicates and no reordering because of the producer retries
+            .set("enable.idempotence", "true")
+            .set(
+                "message.timeout.ms",
+                (config.kafka_message_timeout_secs * 1000).to_string(),
+            )
+            .create()?;
+        Ok(Self {
+            producer,
+            topic: config.kafka_topic.clone(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for KafkaSink {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn store_block(
+        &self,
+        _block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        let payloads = events
+            .iter()
+            .map(|event| serde_json::to_string(&EventMessage::from(event)))
+            .collect::<Result<Vec<_>, _>>()?;
+        // librdkafka retries by itself until `message.timeout.ms` is reached
+        futures::future::try_join_all(events.iter().zip(&payloads).map(|(event, payload)| {
+            self.producer.send(
+                FutureRecord::to(&self.topic)
+                    .key(&event.affected_account_id)
+                    .payload(payload),
+                rdkafka::util::Timeout::Never,
+            )
+        }))
+        .await
+        .map_err(|(err, _)| err)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rdkafka::consumer::{BaseConsumer, Consumer};
+    use rdkafka::Message;
+
+    use super::*;
+    use crate::sinks::EventSink;
+    use crate::test_utils::{balance_event, block_header};
+
+    fn consume(bootstrap_servers: &str, topic: &str, count: usize) -> Vec<(String, EventMessage)> {
+        let consumer: BaseConsumer = rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", bootstrap_servers)
+            .set("group.id", "test")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer.subscribe(&[topic]).unwrap();
+
+        let mut messages = vec![];
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+        while messages.len() < count && std::time::Instant::now() < deadline {
+            if let Some(message) = consumer.poll(std::time::Duration::from_millis(100)) {
+                let message = message.unwrap();
+                messages.push((
+                    String::from_utf8(message.key().unwrap().to_vec()).unwrap(),
+                    serde_json::from_slice(message.payload().unwrap()).unwrap(),
+                ));
+            }
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn publishes_events_keyed_by_account() {
+        let cluster = rdkafka::mocking::MockCluster::new(1).unwrap();
+        cluster.create_topic("balance_events", 1, 1).unwrap();
+        let sink = KafkaSink::new(&crate::configs::KafkaSinkConfig {
+            kafka_brokers: cluster.bootstrap_servers(),
+            kafka_topic: "balance_events".to_string(),
+            kafka_message_timeout_secs: 30,
+        })
+        .unwrap();
+
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let events = vec![
+            balance_event(&header, 0, "alice.near", -5, 10),
+            balance_event(&header, 1, "bob.near", 5, 15),
+        ];
+        sink.store_block(&header, &events).await.unwrap();
+
+        let messages = consume(&cluster.bootstrap_servers(), "balance_events", 2);
+        assert_eq!(
+            messages,
+            events
+                .iter()
+                .map(|event| (event.affected_account_id.clone(), EventMessage::from(event)))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(messages[0].1.block_height, 70_000_000);
+        assert_eq!(messages[0].1.delta_nonstaked_amount, "-5");
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+
+use crate::models::balance_changes::NearBalanceEvent;
+
+/// Events of one block
+pub(crate) type LiveEvents = tokio::sync::broadcast::Sender<Arc<Vec<NearBalanceEvent>>>;
+
+/// Blocks kept for the slow subscribers. The ones which fall behind more are disconnected
+const LIVE_EVENTS_CAPACITY: usize = 1024;
+
+pub(crate) fn live_events() -> LiveEvents {
+    tokio::sync::broadcast::channel(LIVE_EVENTS_CAPACITY).0
+}
+
+/// Passes the events to the subscribers of the API stream.
+/// It goes after all the other sinks, so the events are already committed when the clients get them
+pub(crate) struct LiveSink {
+    live_events: LiveEvents,
+}
+
+impl LiveSink {
+    pub(crate) fn new(live_events: LiveEvents) -> Self {
+        Self { live_events }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for LiveSink {
+    fn name(&self) -> &'static str {
+        "live"
+    }
+
+    async fn store_block(
+        &self,
+        _block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        if !events.is_empty() {
+            // Fails only when nobody listens, that's fine
+            let _ = self.live_events.send(Arc::new(events.to_vec()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+
+use crate::models::balance_changes::NearBalanceEvent;
+
+pub(crate) mod alerts;
+pub(crate) mod clickhouse;
+pub(crate) mod file;
+pub(crate) mod kafka;
+pub(crate) mod live;
+pub(crate) mod parquet;
+pub(crate) mod postgres;
+pub(crate) mod shadow;
+pub(crate) mod sqlite;
+pub(crate) mod webhooks;
+
+/// Destination for the balance events extracted by the indexer
+#[async_trait::async_trait]
+pub(crate) trait EventSink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Stores all the events of the block. Called once per block, even if the block has no events
+    async fn store_block(
+        &self,
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()>;
+
+    /// Block height to resume indexing from, if the sink is able to tell it
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: EventSink + ?Sized> EventSink for std::sync::Arc<T> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    async fn store_block(
+        &self,
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        (**self).store_block(block_header, events).await
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        (**self).start_after_interruption().await
+    }
+}
+
+/// JSON published for every event by `kafka` and `webhooks` sinks. Consumers depend on it:
+/// new fields may be added, existing ones should never be renamed or change their type.
+/// Big numbers are strings, they do not fit into the numbers of most JSON parsers.
+/// It is also `BalanceEvent` type of the GraphQL schema
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    async_graphql::SimpleObject,
+    utoipa::ToSchema,
+    Debug,
+    PartialEq,
+)]
+#[graphql(name = "BalanceEvent", complex)]
+pub(crate) struct EventMessage {
+    pub event_index: String,
+    pub block_timestamp: String,
+    pub block_height: u64,
+    pub receipt_id: Option<String>,
+    pub transaction_hash: Option<String>,
+    pub affected_account_id: String,
+    pub involved_account_id: Option<String>,
+    pub direction: String,
+    pub cause: String,
+    pub status: String,
+    pub delta_nonstaked_amount: String,
+    pub absolute_nonstaked_amount: String,
+    pub delta_staked_amount: String,
+    pub absolute_staked_amount: String,
+    /// The state change in JSON, for `UNKNOWN` cause only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause_payload: Option<String>,
+}
+
+impl From<&NearBalanceEvent> for EventMessage {
+    fn from(event: &NearBalanceEvent) -> Self {
+        Self {
+            event_index: event.event_index.to_string(),
+            block_timestamp: event.block_timestamp.to_string(),
+            block_height: num_traits::ToPrimitive::to_u64(&event.block_height)
+                .expect("block_height should fit into u64"),
+            receipt_id: event.receipt_id.clone(),
+            transaction_hash: event.transaction_hash.clone(),
+            affected_account_id: event.affected_account_id.clone(),
+            involved_account_id: event.involved_account_id.clone(),
+            direction: event.direction.clone(),
+            cause: event.cause.clone(),
+            status: event.status.clone(),
+            delta_nonstaked_amount: event.delta_nonstaked_amount.to_string(),
+            absolute_nonstaked_amount: event.absolute_nonstaked_amount.to_string(),
+            delta_staked_amount: event.delta_staked_amount.to_string(),
+            absolute_staked_amount: event.absolute_staked_amount.to_string(),
+            cause_payload: event.cause_payload.clone(),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SinkKind {
+    Postgres,
+    Jsonl,
+    Csv,
+    Parquet,
+    Sqlite,
+    Clickhouse,
+    Kafka,
+    Webhooks,
+    Alerts,
+    Shadow,
+}
+
+/// All the configured sinks. Every block is passed to them in the order they were configured
+pub(crate) struct Sinks(Vec<Box<dyn EventSink>>);
+
+impl Sinks {
+    pub(crate) async fn from_config(opts: &crate::configs::Opts) -> anyhow::Result<Self> {
+        let mut sinks: Vec<Box<dyn EventSink>> = vec![];
+        for kind in &opts.sinks {
+            let sink: Box<dyn EventSink> = match kind {
+                SinkKind::Postgres => Box::new(
+                    postgres::PostgresSink::connect(
+                        &std::env::var("DATABASE_URL")?,
+                        &opts.postgres_sink,
+                    )
+                    .await?,
+                ),
+                SinkKind::Jsonl => Box::new(file::FileSink::new(
+                    file::FileFormat::JsonLines,
+                    &opts.file_sink,
+                )?),
+                SinkKind::Csv => {
+                    Box::new(file::FileSink::new(file::FileFormat::Csv, &opts.file_sink)?)
+                }
+                SinkKind::Parquet => Box::new(parquet::ParquetSink::new(&opts.parquet_sink)?),
+                SinkKind::Sqlite => {
+                    Box::new(sqlite::SqliteSink::connect(&opts.sqlite_database_url).await?)
+                }
+                SinkKind::Clickhouse => {
+                    Box::new(clickhouse::ClickHouseSink::connect(&opts.clickhouse_sink).await?)
+                }
+                SinkKind::Kafka => Box::new(kafka::KafkaSink::new(&opts.kafka_sink)?),
+                SinkKind::Webhooks => Box::new(webhooks::WebhooksSink::new(
+                    &opts.webhooks,
+                    Some(sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?),
+                )?),
+                SinkKind::Alerts => {
+                    Box::new(alerts::AlertsSink::connect(&opts.alert_rules, &opts.webhooks).await?)
+                }
+                SinkKind::Shadow => {
+                    Box::new(shadow::ShadowSink::connect(&std::env::var("DATABASE_URL")?).await?)
+                }
+            };
+            tracing::info!(target: crate::LOGGING_PREFIX, "Sink enabled: {}", sink.name());
+            sinks.push(sink);
+        }
+        if sinks.is_empty() {
+            anyhow::bail!("At least one sink should be configured");
+        }
+        Ok(Self(sinks))
+    }
+
+    pub(crate) fn new(sink: Box<dyn EventSink>) -> Self {
+        Self(vec![sink])
+    }
+
+    /// For the commands which fix the data in Postgres, the other sinks are not touched
+    pub(crate) async fn postgres_only(opts: &crate::configs::Opts) -> anyhow::Result<Self> {
+        Ok(Self(vec![Box::new(
+            postgres::PostgresSink::connect(&std::env::var("DATABASE_URL")?, &opts.postgres_sink)
+                .await?,
+        )]))
+    }
+
+    /// Adds the sink which is not configured with `SINKS`
+    pub(crate) fn push(&mut self, sink: Box<dyn EventSink>) {
+        tracing::info!(target: crate::LOGGING_PREFIX, "Sink enabled: {}", sink.name());
+        self.0.push(sink);
+    }
+
+    pub(crate) async fn store_block(
+        &self,
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        for sink in &self.0 {
+            sink.store_block(block_header, events)
+                .await
+                .map_err(|err| anyhow::anyhow!("Sink {} failed: {}", sink.name(), err))?;
+        }
+        Ok(())
+    }
+
+    // We start from the sink which is the most behind, so that none of them misses the blocks.
+    // The sinks which are ahead should tolerate the blocks they have already seen
+    pub(crate) async fn start_after_interruption(&self) -> anyhow::Result<u64> {
+        let mut result: Option<u64> = None;
+        for sink in &self.0 {
+            if let Some(height) = sink.start_after_interruption().await? {
+                result = Some(result.map_or(height, |current| current.min(height)));
+            }
+        }
+        result.ok_or_else(|| {
+            anyhow::anyhow!("`START_BLOCK_HEIGHT` should be provided, none of the sinks can tell where to resume from")
+        })
+    }
+}
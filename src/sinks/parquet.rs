@@ -0,0 +1,568 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bigdecimal::BigDecimal;
+use chrono::{Datelike, NaiveDate};
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+use num_traits::{Signed, ToPrimitive};
+use parquet::data_type::{ByteArray, ByteArrayType, FixedLenByteArray, FixedLenByteArrayType};
+use parquet::file::writer::{
+    SerializedColumnWriter, SerializedFileWriter, SerializedRowGroupWriter,
+};
+
+use crate::models::balance_changes::NearBalanceEvent;
+
+const NANOS_IN_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const DAYS_FROM_CE_TO_UNIX_EPOCH: i32 = 719_163;
+const FILE_PREFIX: &str = "near_balance_events_";
+
+// Amounts are up to 40 digits, it's more than Decimal128 could store
+// https://github.com/apache/parquet-format/blob/master/LogicalTypes.md#decimal
+const AMOUNT_BYTES: usize = 17;
+const EVENT_INDEX_BYTES: usize = 16;
+const SCHEMA: &str = "
+    message near_balance_events {
+        REQUIRED FIXED_LEN_BYTE_ARRAY (16) event_index (DECIMAL(38, 0));
+        REQUIRED INT64 block_timestamp (TIMESTAMP(NANOS, true));
+        REQUIRED INT64 block_height;
+        OPTIONAL BYTE_ARRAY receipt_id (UTF8);
+        OPTIONAL BYTE_ARRAY transaction_hash (UTF8);
+        REQUIRED BYTE_ARRAY affected_account_id (UTF8);
+        OPTIONAL BYTE_ARRAY involved_account_id (UTF8);
+        REQUIRED BYTE_ARRAY direction (UTF8);
+        REQUIRED BYTE_ARRAY cause (UTF8);
+        REQUIRED BYTE_ARRAY status (UTF8);
+        REQUIRED FIXED_LEN_BYTE_ARRAY (17) delta_nonstaked_amount (DECIMAL(40, 0));
+        REQUIRED FIXED_LEN_BYTE_ARRAY (17) absolute_nonstaked_amount (DECIMAL(40, 0));
+        REQUIRED FIXED_LEN_BYTE_ARRAY (17) delta_staked_amount (DECIMAL(40, 0));
+        REQUIRED FIXED_LEN_BYTE_ARRAY (17) absolute_staked_amount (DECIMAL(40, 0));
+    }
+";
+
+/// UTC day of the block timestamp given in nanoseconds
+pub(crate) fn utc_day(timestamp_nanosec: u64) -> NaiveDate {
+    NaiveDate::from_num_days_from_ce_opt(
+        DAYS_FROM_CE_TO_UNIX_EPOCH + (timestamp_nanosec / NANOS_IN_DAY) as i32,
+    )
+    .expect("Block timestamp is out of range")
+}
+
+/// Timestamp in nanoseconds of the beginning of the UTC day
+pub(crate) fn day_start_nanosec(day: NaiveDate) -> u64 {
+    (day.num_days_from_ce() - DAYS_FROM_CE_TO_UNIX_EPOCH) as u64 * NANOS_IN_DAY
+}
+
+// The file being written right now. It gets its final name only when it's closed,
+// so the half-written files are never picked up by the readers
+struct Part {
+    day: NaiveDate,
+    first_block_height: u64,
+    last_block_height: u64,
+    tmp_path: PathBuf,
+    writer: SerializedFileWriter<std::fs::File>,
+    buffer: Vec<NearBalanceEvent>,
+    rows: usize,
+}
+
+/// Writes the events to `<dir>/date=YYYY-MM-DD/near_balance_events_<first block>_<last block>.parquet`.
+/// A file is closed when the day is over or when it reaches `max_rows_per_file` rows
+pub(crate) struct ParquetWriter {
+    config: crate::configs::ParquetSinkConfig,
+    part: Option<Part>,
+}
+
+impl ParquetWriter {
+    pub(crate) fn new(config: &crate::configs::ParquetSinkConfig) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&config.parquet_dir)?;
+        Ok(Self {
+            config: config.clone(),
+            part: None,
+        })
+    }
+
+    pub(crate) fn write_block(
+        &mut self,
+        block_height: u64,
+        block_timestamp: u64,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        let day = utc_day(block_timestamp);
+        if let Some(part) = self.part.as_ref() {
+            if part.day != day || part.rows >= self.config.parquet_max_rows_per_file {
+                self.finish()?;
+            }
+        }
+        if events.is_empty() {
+            if let Some(part) = self.part.as_mut() {
+                part.last_block_height = block_height;
+            }
+            return Ok(());
+        }
+
+        if self.part.is_none() {
+            self.part = Some(self.open(day, block_height)?);
+        }
+        let part = self.part.as_mut().expect("Part was opened above");
+        part.last_block_height = block_height;
+        part.rows += events.len();
+        part.buffer.extend_from_slice(events);
+        if part.buffer.len() >= self.config.parquet_row_group_size {
+            write_row_group(&mut part.writer, &part.buffer)?;
+            part.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Closes the current file, if any
+    pub(crate) fn finish(&mut self) -> anyhow::Result<()> {
+        let mut part = match self.part.take() {
+            Some(part) => part,
+            None => return Ok(()),
+        };
+        if !part.buffer.is_empty() {
+            write_row_group(&mut part.writer, &part.buffer)?;
+        }
+        part.writer.close()?;
+
+        let path = self
+            .config
+            .parquet_dir
+            .join(partition_name(part.day))
+            .join(format!(
+                "{}{:012}_{:012}.parquet",
+                FILE_PREFIX, part.first_block_height, part.last_block_height
+            ));
+        std::fs::rename(&part.tmp_path, &path)?;
+        tracing::info!(
+            target: crate::LOGGING_PREFIX,
+            "Parquet file {} is written, {} rows",
+            path.display(),
+            part.rows
+        );
+        Ok(())
+    }
+
+    fn open(&self, day: NaiveDate, first_block_height: u64) -> anyhow::Result<Part> {
+        let dir = self.config.parquet_dir.join(partition_name(day));
+        std::fs::create_dir_all(&dir)?;
+        let tmp_path = dir.join(format!(
+            "{}{:012}.parquet.tmp",
+            FILE_PREFIX, first_block_height
+        ));
+
+        let schema = Arc::new(parquet::schema::parser::parse_message_type(SCHEMA)?);
+        let properties = Arc::new(
+            parquet::file::properties::WriterProperties::builder()
+                .set_compression(parquet::basic::Compression::SNAPPY)
+                .build(),
+        );
+        let writer =
+            SerializedFileWriter::new(std::fs::File::create(&tmp_path)?, schema, properties)?;
+
+        Ok(Part {
+            day,
+            first_block_height,
+            last_block_height: first_block_height,
+            tmp_path,
+            writer,
+            buffer: vec![],
+            rows: 0,
+        })
+    }
+}
+
+/// Continues the live writing from the block after the last finished file
+pub(crate) struct ParquetSink {
+    writer: std::sync::Mutex<ParquetWriter>,
+    last_written_block_height: Option<u64>,
+    /// The first block of the earliest unfinished file removed on start, it should be written again
+    unfinished_block_height: Option<u64>,
+}
+
+impl ParquetSink {
+    pub(crate) fn new(config: &crate::configs::ParquetSinkConfig) -> anyhow::Result<Self> {
+        let writer = ParquetWriter::new(config)?;
+        // Leftovers of the previous run, they were never finished and can't be read
+        let mut unfinished_block_height: Option<u64> = None;
+        for path in unfinished_files(&config.parquet_dir)? {
+            tracing::warn!(
+                target: crate::LOGGING_PREFIX,
+                "Removing unfinished parquet file {}",
+                path.display()
+            );
+            if let Some(height) = first_block_height_from_tmp_file_name(&path) {
+                unfinished_block_height =
+                    Some(unfinished_block_height.map_or(height, |current| current.min(height)));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let last_written_block_height = partition_files(&config.parquet_dir)?
+            .iter()
+            .filter_map(|path| last_block_height_from_file_name(path))
+            .max();
+        Ok(Self {
+            writer: std::sync::Mutex::new(writer),
+            last_written_block_height,
+            unfinished_block_height,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for ParquetSink {
+    fn name(&self) -> &'static str {
+        "parquet"
+    }
+
+    async fn store_block(
+        &self,
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        // Other sinks may ask to start earlier, we don't want the duplicates in the files
+        if matches!(self.last_written_block_height, Some(height) if block_header.height <= height) {
+            return Ok(());
+        }
+        self.writer
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Parquet sink lock is poisoned"))?
+            .write_block(block_header.height, block_header.timestamp_nanosec, events)
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        // The unfinished file always starts after the finished ones
+        Ok(self
+            .last_written_block_height
+            .map(|height| height + 1)
+            .or(self.unfinished_block_height))
+    }
+}
+
+fn partition_name(day: NaiveDate) -> String {
+    format!("date={}", day.format("%Y-%m-%d"))
+}
+
+fn partition_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for partition in std::fs::read_dir(dir)? {
+        let partition = partition?.path();
+        if !partition.is_dir() {
+            continue;
+        }
+        for file in std::fs::read_dir(&partition)? {
+            files.push(file?.path());
+        }
+    }
+    Ok(files)
+}
+
+/// The files of the parts which are being written, or were never finished
+pub(crate) fn unfinished_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    Ok(partition_files(dir)?
+        .into_iter()
+        .filter(|path| path.extension().map_or(false, |ext| ext == "tmp"))
+        .collect())
+}
+
+// `near_balance_events_000000000010_000000000020.parquet` -> 20
+fn last_block_height_from_file_name(path: &Path) -> Option<u64> {
+    if path.extension()? != "parquet" {
+        return None;
+    }
+    path.file_stem()?
+        .to_str()?
+        .strip_prefix(FILE_PREFIX)?
+        .split('_')
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+// `near_balance_events_000000000010.parquet.tmp` -> 10
+fn first_block_height_from_tmp_file_name(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix(FILE_PREFIX)?
+        .strip_suffix(".parquet.tmp")?
+        .parse()
+        .ok()
+}
+
+fn write_row_group(
+    writer: &mut SerializedFileWriter<std::fs::File>,
+    events: &[NearBalanceEvent],
+) -> anyhow::Result<()> {
+    let mut row_group = writer.next_row_group()?;
+    write_decimals(&mut row_group, events, EVENT_INDEX_BYTES, |e| {
+        &e.event_index
+    })?;
+    write_int64(&mut row_group, events, |e| &e.block_timestamp)?;
+    write_int64(&mut row_group, events, |e| &e.block_height)?;
+    write_optional_strings(&mut row_group, events, |e| &e.receipt_id)?;
+    write_optional_strings(&mut row_group, events, |e| &e.transaction_hash)?;
+    write_strings(&mut row_group, events, |e| &e.affected_account_id)?;
+    write_optional_strings(&mut row_group, events, |e| &e.involved_account_id)?;
+    write_strings(&mut row_group, events, |e| &e.direction)?;
+    write_strings(&mut row_group, events, |e| &e.cause)?;
+    write_strings(&mut row_group, events, |e| &e.status)?;
+    write_decimals(&mut row_group, events, AMOUNT_BYTES, |e| {
+        &e.delta_nonstaked_amount
+    })?;
+    write_decimals(&mut row_group, events, AMOUNT_BYTES, |e| {
+        &e.absolute_nonstaked_amount
+    })?;
+    write_decimals(&mut row_group, events, AMOUNT_BYTES, |e| {
+        &e.delta_staked_amount
+    })?;
+    write_decimals(&mut row_group, events, AMOUNT_BYTES, |e| {
+        &e.absolute_staked_amount
+    })?;
+    row_group.close()?;
+    Ok(())
+}
+
+fn next_column<'a>(
+    row_group: &'a mut SerializedRowGroupWriter<'_, std::fs::File>,
+) -> anyhow::Result<SerializedColumnWriter<'a>> {
+    row_group
+        .next_column()?
+        .ok_or_else(|| anyhow::anyhow!("Parquet schema has less columns than NearBalanceEvent"))
+}
+
+fn write_decimals(
+    row_group: &mut SerializedRowGroupWriter<'_, std::fs::File>,
+    events: &[NearBalanceEvent],
+    bytes: usize,
+    field: impl Fn(&NearBalanceEvent) -> &BigDecimal,
+) -> anyhow::Result<()> {
+    let values = events
+        .iter()
+        .map(|event| to_fixed_len_decimal(field(event), bytes))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut column = next_column(row_group)?;
+    column
+        .typed::<FixedLenByteArrayType>()
+        .write_batch(&values, None, None)?;
+    column.close()?;
+    Ok(())
+}
+
+fn write_int64(
+    row_group: &mut SerializedRowGroupWriter<'_, std::fs::File>,
+    events: &[NearBalanceEvent],
+    field: impl Fn(&NearBalanceEvent) -> &BigDecimal,
+) -> anyhow::Result<()> {
+    let values = events
+        .iter()
+        .map(|event| {
+            field(event)
+                .to_i64()
+                .ok_or_else(|| anyhow::anyhow!("{} does not fit into INT64", field(event)))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut column = next_column(row_group)?;
+    column
+        .typed::<parquet::data_type::Int64Type>()
+        .write_batch(&values, None, None)?;
+    column.close()?;
+    Ok(())
+}
+
+fn write_strings(
+    row_group: &mut SerializedRowGroupWriter<'_, std::fs::File>,
+    events: &[NearBalanceEvent],
+    field: impl Fn(&NearBalanceEvent) -> &String,
+) -> anyhow::Result<()> {
+    let values: Vec<ByteArray> = events
+        .iter()
+        .map(|event| field(event).as_str().into())
+        .collect();
+    let mut column = next_column(row_group)?;
+    column
+        .typed::<ByteArrayType>()
+        .write_batch(&values, None, None)?;
+    column.close()?;
+    Ok(())
+}
+
+fn write_optional_strings(
+    row_group: &mut SerializedRowGroupWriter<'_, std::fs::File>,
+    events: &[NearBalanceEvent],
+    field: impl Fn(&NearBalanceEvent) -> &Option<String>,
+) -> anyhow::Result<()> {
+    let values: Vec<ByteArray> = events
+        .iter()
+        .filter_map(|event| field(event).as_deref().map(ByteArray::from))
+        .collect();
+    let definition_levels: Vec<i16> = events
+        .iter()
+        .map(|event| i16::from(field(event).is_some()))
+        .collect();
+    let mut column = next_column(row_group)?;
+    column
+        .typed::<ByteArrayType>()
+        .write_batch(&values, Some(&definition_levels), None)?;
+    column.close()?;
+    Ok(())
+}
+
+// Big-endian two's complement of the unscaled value, sign-extended to the given length
+fn to_fixed_len_decimal(value: &BigDecimal, bytes: usize) -> anyhow::Result<FixedLenByteArray> {
+    let (unscaled, _) = value.with_scale(0).as_bigint_and_exponent();
+    let value_bytes = unscaled.to_signed_bytes_be();
+    if value_bytes.len() > bytes {
+        anyhow::bail!("{} does not fit into {} bytes", value, bytes);
+    }
+    let fill = if unscaled.is_negative() { 0xff } else { 0 };
+    let mut result = vec![fill; bytes - value_bytes.len()];
+    result.extend(value_bytes);
+    Ok(result.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use parquet::file::reader::FileReader;
+    use parquet::record::RowAccessor;
+
+    use super::*;
+
+    fn event(block_height: u64, block_timestamp: u64, delta: &str) -> NearBalanceEvent {
+        NearBalanceEvent {
+            event_index: (u128::from(block_timestamp) * 100_000_000 * 100_000_000)
+                .to_string()
+                .parse()
+                .unwrap(),
+            block_timestamp: block_timestamp.into(),
+            block_height: block_height.into(),
+            receipt_id: Some("7nu7ZAK3T11erEgG8aWTRGmz9uTHGazoNMjJdVyG3piX".to_string()),
+            transaction_hash: None,
+            affected_account_id: "alice.near".to_string(),
+            involved_account_id: None,
+            direction: "INBOUND".to_string(),
+            cause: "RECEIPT".to_string(),
+            status: "SUCCESS".to_string(),
+            delta_nonstaked_amount: BigDecimal::from_str(delta).unwrap(),
+            absolute_nonstaked_amount: BigDecimal::from_str(
+                "9999999999999999999999999999999999999999",
+            )
+            .unwrap(),
+            delta_staked_amount: 0.into(),
+            absolute_staked_amount: 0.into(),
+            cause_payload: None,
+        }
+    }
+
+    #[test]
+    fn encodes_decimals_as_twos_complement() {
+        let value = |s: &str| {
+            to_fixed_len_decimal(&BigDecimal::from_str(s).unwrap(), 4)
+                .unwrap()
+                .data()
+                .to_vec()
+        };
+        assert_eq!(value("0"), vec![0, 0, 0, 0]);
+        assert_eq!(value("258"), vec![0, 0, 1, 2]);
+        assert_eq!(value("-1"), vec![0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(value("-256"), vec![0xff, 0xff, 0xff, 0]);
+        assert!(to_fixed_len_decimal(&BigDecimal::from_str("4294967296").unwrap(), 4).is_err());
+    }
+
+    #[test]
+    fn partitions_by_utc_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = crate::configs::ParquetSinkConfig {
+            parquet_dir: dir.path().to_path_buf(),
+            parquet_row_group_size: 2,
+            parquet_max_rows_per_file: 1_000_000,
+        };
+        // 2022-07-05 23:59:59 and 2022-07-06 00:00:01 UTC
+        let evening = 1_657_065_599_000_000_000;
+        let morning = 1_657_065_601_000_000_000;
+
+        let mut writer = ParquetWriter::new(&config).unwrap();
+        writer
+            .write_block(
+                10,
+                evening,
+                &[event(10, evening, "-1"), event(10, evening, "5")],
+            )
+            .unwrap();
+        writer
+            .write_block(11, evening, &[event(11, evening, "7")])
+            .unwrap();
+        writer
+            .write_block(12, morning, &[event(12, morning, "1")])
+            .unwrap();
+        writer.write_block(13, morning, &[]).unwrap();
+        writer.finish().unwrap();
+
+        let first = dir
+            .path()
+            .join("date=2022-07-05/near_balance_events_000000000010_000000000011.parquet");
+        let second = dir
+            .path()
+            .join("date=2022-07-06/near_balance_events_000000000012_000000000013.parquet");
+        let reader =
+            parquet::file::reader::SerializedFileReader::new(std::fs::File::open(&first).unwrap())
+                .unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        assert_eq!(row.get_string(5).unwrap(), "alice.near");
+        assert_eq!(row.get_long(2).unwrap(), 10);
+        assert_eq!(
+            row.get_decimal(10).unwrap().data(),
+            &[0xff; AMOUNT_BYTES][..],
+            "delta_nonstaked_amount should be -1"
+        );
+        assert!(second.exists());
+
+        let sink = ParquetSink::new(&config).unwrap();
+        assert_eq!(sink.last_written_block_height, Some(13));
+    }
+
+    #[tokio::test]
+    async fn removes_unfinished_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = crate::configs::ParquetSinkConfig {
+            parquet_dir: dir.path().to_path_buf(),
+            parquet_row_group_size: 100,
+            parquet_max_rows_per_file: 1_000_000,
+        };
+        let timestamp = 1_657_065_599_000_000_000;
+
+        let mut writer = ParquetWriter::new(&config).unwrap();
+        writer
+            .write_block(10, timestamp, &[event(10, timestamp, "1")])
+            .unwrap();
+        // The process dies here, the file is never finished
+        drop(writer);
+        // Only the sink owns the directory, another writer leaves the part alone
+        ParquetWriter::new(&config).unwrap();
+        assert_eq!(unfinished_files(dir.path()).unwrap().len(), 1);
+
+        let sink = ParquetSink::new(&config).unwrap();
+        assert_eq!(sink.last_written_block_height, None);
+        assert!(partition_files(dir.path()).unwrap().is_empty());
+        // The removed rows are written again
+        assert_eq!(
+            crate::sinks::EventSink::start_after_interruption(&sink)
+                .await
+                .unwrap(),
+            Some(10)
+        );
+    }
+
+    #[test]
+    fn converts_day_boundaries() {
+        let day = NaiveDate::from_ymd_opt(2022, 7, 6).unwrap();
+        assert_eq!(day_start_nanosec(day), 1_657_065_600_000_000_000);
+        assert_eq!(utc_day(1_657_065_600_000_000_000), day);
+        assert_eq!(
+            utc_day(1_657_065_599_999_999_999),
+            NaiveDate::from_ymd_opt(2022, 7, 5).unwrap()
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+
+use crate::models::account_balances_latest::AccountBalanceLatest;
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::models::block_stats::NearBalanceBlockStats;
+use crate::models::outbox::NearBalanceEventsOutbox;
+use crate::models::processed_blocks::NearBalanceProcessedBlock;
+
+/// Writes the events to `near_balance_events` table, the summary of the block to `near_balance_block_stats`,
+/// the record about the block to `near_balance_processed_blocks`, and keeps `account_balances_latest` up to date.
+/// All the rows of the block are written in one transaction
+pub(crate) struct PostgresSink {
+    pool: sqlx::Pool<sqlx::Postgres>,
+    config: crate::configs::PostgresSinkConfig,
+}
+
+impl PostgresSink {
+    pub(crate) async fn connect(
+        database_url: &str,
+        config: &crate::configs::PostgresSinkConfig,
+    ) -> anyhow::Result<Self> {
+        let pool = sqlx::PgPool::connect(database_url).await?;
+        // TODO Error: while executing migrations: error returned from database: 1128 (HY000): Function 'near_indexer.GET_LOCK' is not defined
+        // sqlx::migrate!().run(&pool).await?;
+        Ok(Self {
+            pool,
+            config: config.clone(),
+        })
+    }
+
+    async fn store_block_in_transaction(
+        &self,
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+        outbox: Option<&NearBalanceEventsOutbox>,
+    ) -> anyhow::Result<()> {
+        let mut transaction = self.pool.begin().await?;
+        if self.config.postgres_verify_continuity {
+            let breaks =
+                crate::db_adapters::continuity::check_block(&mut transaction, events).await?;
+            for continuity_break in breaks {
+                crate::metrics::CONTINUITY_BREAKS_TOTAL.inc();
+                tracing::warn!(
+                    target: crate::LOGGING_PREFIX,
+                    "Balance of {} does not continue at block {}: {}",
+                    continuity_break.account_id,
+                    block_header.height,
+                    serde_json::to_string(&continuity_break)?,
+                );
+            }
+        }
+        crate::models::chunked_insert_with_connection::<sqlx::Postgres, _>(
+            &mut *transaction,
+            events,
+        )
+        .await?;
+        crate::models::chunked_insert_with_connection::<sqlx::Postgres, _>(
+            &mut *transaction,
+            &AccountBalanceLatest::from_events(events),
+        )
+        .await?;
+        crate::models::chunked_insert_with_connection::<sqlx::Postgres, _>(
+            &mut *transaction,
+            &[NearBalanceBlockStats::from_block(block_header, events)?],
+        )
+        .await?;
+        crate::models::chunked_insert_with_connection::<sqlx::Postgres, _>(
+            &mut *transaction,
+            &[NearBalanceProcessedBlock::from_block(block_header)],
+        )
+        .await?;
+        if self.config.postgres_buckets && !events.is_empty() {
+            crate::db_adapters::balance_buckets::update_buckets(
+                &mut transaction,
+                block_header.height,
+            )
+            .await?;
+        }
+        if let Some(outbox) = outbox {
+            if self.config.postgres_outbox {
+                crate::models::chunked_insert_with_connection::<sqlx::Postgres, _>(
+                    &mut *transaction,
+                    std::slice::from_ref(outbox),
+                )
+                .await?;
+            }
+            // Postgres delivers the notification only when the transaction is committed
+            if let Some(channel) = &self.config.postgres_notify_channel {
+                sqlx::query("SELECT pg_notify($1, $2)")
+                    .bind(channel)
+                    .bind(serde_json::to_string(outbox)?)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for PostgresSink {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn store_block(
+        &self,
+        block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        let outbox = NearBalanceEventsOutbox::from_block(block_header, events);
+        let mut interval = crate::INTERVAL;
+        let mut retry_attempt = 0usize;
+
+        loop {
+            if retry_attempt == crate::RETRY_COUNT {
+                anyhow::bail!(
+                    "Failed to store block {} to Postgres after {} attempts. Stop trying.",
+                    block_header.height,
+                    crate::RETRY_COUNT
+                );
+            }
+            retry_attempt += 1;
+
+            match self
+                .store_block_in_transaction(block_header, events, outbox.as_ref())
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    tracing::error!(
+                        target: crate::LOGGING_PREFIX,
+                        "Error occurred during storing block {}:\n{}\n Retrying in {} milliseconds...",
+                        block_header.height,
+                        err,
+                        interval.as_millis(),
+                    );
+                    tokio::time::sleep(interval).await;
+                    if interval < crate::MAX_DELAY_TIME {
+                        interval *= 2;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        crate::models::start_after_interruption(&self.pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::EventSink;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    #[tokio::test]
+    #[ignore]
+    async fn writes_outbox_and_notifies_on_commit() {
+        let (_, schema) = test_pool().await;
+        let sink = PostgresSink::connect(
+            &schema.url,
+            &crate::configs::PostgresSinkConfig {
+                postgres_outbox: true,
+                postgres_notify_channel: Some("near_balance_events_test".to_string()),
+                postgres_buckets: false,
+                postgres_verify_continuity: false,
+            },
+        )
+        .await
+        .unwrap();
+        let mut listener = sqlx::postgres::PgListener::connect(&schema.url)
+            .await
+            .unwrap();
+        listener.listen("near_balance_events_test").await.unwrap();
+
+        let header = block_header(1, 1_000_000_000);
+        let events = vec![
+            balance_event(&header, 0, "alice.near", -5, 10),
+            balance_event(&header, 1, "bob.near", 5, 15),
+        ];
+        sink.store_block(&header, &events).await.unwrap();
+        // Blocks without events are not announced
+        sink.store_block(&block_header(2, 2_000_000_000), &[])
+            .await
+            .unwrap();
+
+        let expected = NearBalanceEventsOutbox::from_block(&header, &events).unwrap();
+        let notification = listener.recv().await.unwrap();
+        assert_eq!(
+            notification.payload(),
+            serde_json::to_string(&expected).unwrap()
+        );
+        let outbox: NearBalanceEventsOutbox = sqlx::query_as(
+            "SELECT block_height, block_timestamp, first_event_index, last_event_index, events_count
+             FROM near_balance_events_outbox",
+        )
+        .fetch_one(&sink.pool)
+        .await
+        .unwrap();
+        assert_eq!(outbox, expected);
+        assert_eq!(outbox.first_event_index, events[0].event_index);
+        assert_eq!(outbox.last_event_index, events[1].event_index);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn upserts_latest_balances() {
+        let (_, schema) = test_pool().await;
+        let sink = PostgresSink::connect(
+            &schema.url,
+            &crate::configs::PostgresSinkConfig {
+                postgres_outbox: false,
+                postgres_notify_channel: None,
+                postgres_buckets: false,
+                postgres_verify_continuity: false,
+            },
+        )
+        .await
+        .unwrap();
+        let first = block_header(1, 1_000_000_000);
+        let second = block_header(2, 2_000_000_000);
+
+        let first_events = vec![
+            balance_event(&first, 0, "alice.near", -5, 10),
+            balance_event(&first, 1, "bob.near", 5, 15),
+        ];
+        sink.store_block(&first, &first_events).await.unwrap();
+        sink.store_block(&second, &[balance_event(&second, 0, "alice.near", -3, 7)])
+            .await
+            .unwrap();
+        // The replayed block does not move the balance back
+        sink.store_block(&first, &first_events).await.unwrap();
+
+        let balances: Vec<AccountBalanceLatest> = sqlx::query_as(
+            "SELECT account_id, nonstaked_amount, staked_amount, last_event_index, last_block_height
+             FROM account_balances_latest ORDER BY account_id",
+        )
+        .fetch_all(&sink.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            balances
+                .iter()
+                .map(|balance| (
+                    balance.account_id.as_str(),
+                    balance.nonstaked_amount.clone(),
+                    balance.last_block_height.clone()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("alice.near", 7.into(), 2.into()),
+                ("bob.near", 15.into(), 1.into()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn writes_block_stats() {
+        let (_, schema) = test_pool().await;
+        let sink = PostgresSink::connect(
+            &schema.url,
+            &crate::configs::PostgresSinkConfig {
+                postgres_outbox: false,
+                postgres_notify_channel: None,
+                postgres_buckets: false,
+                postgres_verify_continuity: false,
+            },
+        )
+        .await
+        .unwrap();
+        let header = block_header(1, 1_000_000_000);
+
+        let events = vec![
+            balance_event(&header, 0, "alice.near", -5, 10),
+            balance_event(&header, 1, "bob.near", 5, 15),
+        ];
+        sink.store_block(&header, &events).await.unwrap();
+        let stats: NearBalanceBlockStats = sqlx::query_as(
+            "SELECT block_height, block_timestamp, nonstaked_inflow, nonstaked_outflow, staked_inflow, staked_outflow,
+                validators_reward, contract_reward, affected_accounts_count, total_supply
+             FROM near_balance_block_stats",
+        )
+        .fetch_one(&sink.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            stats,
+            NearBalanceBlockStats::from_block(&header, &events).unwrap()
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+use sqlx::database::HasArguments;
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::models::{FieldCount, SqlxMethods};
+
+/// Writes the events to `near_balance_events_shadow`, the table of the same structure as `near_balance_events`.
+/// Used to compare the output of the new extraction logic with the stored one before deploying it
+pub(crate) struct ShadowSink {
+    pool: sqlx::Pool<sqlx::Postgres>,
+}
+
+impl ShadowSink {
+    pub(crate) async fn connect(database_url: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            pool: sqlx::PgPool::connect(database_url).await?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ShadowEvent<'a>(&'a NearBalanceEvent);
+
+impl<'a> SqlxMethods<sqlx::Postgres> for ShadowEvent<'a> {
+    fn add_to_args<'q>(&'q self, args: &mut <sqlx::Postgres as HasArguments<'q>>::Arguments) {
+        SqlxMethods::<sqlx::Postgres>::add_to_args(self.0, args);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO near_balance_events_shadow {} VALUES ",
+            crate::models::balance_changes::INSERT_COLUMNS
+        ) + &crate::models::create_placeholders(count, NearBalanceEvent::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "near_balance_events_shadow".to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for ShadowSink {
+    fn name(&self) -> &'static str {
+        "shadow"
+    }
+
+    async fn store_block(
+        &self,
+        _block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        let events: Vec<ShadowEvent> = events.iter().map(ShadowEvent).collect();
+        crate::models::chunked_insert(&self.pool, &events, crate::RETRY_COUNT).await
+    }
+}
@@ -0,0 +1,104 @@
+use std::str::FromStr;
+
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+
+use crate::models::balance_changes::NearBalanceEvent;
+
+/// Writes the events to `near_balance_events` table in the SQLite database file
+pub(crate) struct SqliteSink {
+    pool: sqlx::Pool<sqlx::Sqlite>,
+}
+
+impl SqliteSink {
+    pub(crate) async fn connect(database_url: &str) -> anyhow::Result<Self> {
+        let options = sqlx::sqlite::SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
+        let pool = sqlx::SqlitePool::connect_with(options).await?;
+        sqlx::migrate!("migrations/sqlite").run(&pool).await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn store_block(
+        &self,
+        _block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        crate::models::chunked_insert(&self.pool, events, crate::RETRY_COUNT).await
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        crate::models::start_after_interruption(&self.pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Row;
+
+    use super::*;
+    use crate::sinks::EventSink;
+    use crate::test_utils::{balance_event, block_header};
+
+    #[tokio::test]
+    async fn stores_events_and_resumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = SqliteSink::connect(&format!(
+            "sqlite://{}",
+            dir.path().join("balances.sqlite").display()
+        ))
+        .await
+        .unwrap();
+        assert_eq!(sink.start_after_interruption().await.unwrap(), None);
+
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let events = vec![
+            balance_event(
+                &header,
+                0,
+                "alice.near",
+                -12_500_000_000_000_000_000_000_000,
+                0,
+            ),
+            balance_event(
+                &header,
+                1,
+                "bob.near",
+                3,
+                1_000_012_500_000_000_000_000_000_000_000,
+            ),
+        ];
+        sink.store_block(&header, &events).await.unwrap();
+        // The same block after the restart is not duplicated
+        sink.store_block(&header, &events).await.unwrap();
+
+        let rows = sqlx::query(
+            "SELECT event_index, delta_nonstaked_amount, absolute_nonstaked_amount
+            FROM near_balance_events ORDER BY event_index",
+        )
+        .fetch_all(&sink.pool)
+        .await
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0].get::<String, _>(0),
+            "16570000000000000000000000000000000"
+        );
+        assert_eq!(rows[0].get::<String, _>(1), "-12500000000000000000000000");
+        assert_eq!(
+            rows[1].get::<String, _>(2),
+            "1000012500000000000000000000000"
+        );
+        assert_eq!(
+            sink.start_after_interruption().await.unwrap(),
+            Some(70_000_000 - 1000)
+        );
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use futures::StreamExt;
+use hmac::Mac;
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::models::balance_changes::NearBalanceEvent;
+use crate::models::webhook_dead_letters::WebhookDeadLetter;
+use crate::sinks::EventMessage;
+
+/// Entry of the watchlist file
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct Webhook {
+    pub url: String,
+    /// Key for HMAC-SHA256 signature of the payload
+    pub secret: String,
+    #[serde(default)]
+    pub accounts: Vec<String>,
+}
+
+struct Delivery {
+    webhook: Arc<Webhook>,
+    block_height: u64,
+    event_index: bigdecimal::BigDecimal,
+    affected_account_id: String,
+    payload: String,
+}
+
+/// Block heights of the unfinished deliveries, with the number of deliveries of each block
+#[derive(Default)]
+struct PendingDeliveries {
+    blocks: std::sync::Mutex<BTreeMap<u64, usize>>,
+    finished: tokio::sync::Notify,
+}
+
+impl PendingDeliveries {
+    fn add(&self, block_height: u64) {
+        *self
+            .blocks
+            .lock()
+            .expect("Pending deliveries lock is poisoned")
+            .entry(block_height)
+            .or_default() += 1;
+    }
+
+    fn finish(&self, block_height: u64) {
+        let mut blocks = self
+            .blocks
+            .lock()
+            .expect("Pending deliveries lock is poisoned");
+        if let Some(count) = blocks.get_mut(&block_height) {
+            *count -= 1;
+            if *count == 0 {
+                blocks.remove(&block_height);
+            }
+        }
+        drop(blocks);
+        self.finished.notify_waiters();
+    }
+
+    fn oldest(&self) -> Option<u64> {
+        self.blocks
+            .lock()
+            .expect("Pending deliveries lock is poisoned")
+            .keys()
+            .next()
+            .copied()
+    }
+
+    /// Waits until the restart from `block_height` replays the blocks of all the unfinished deliveries
+    async fn wait_for_replay_window(&self, block_height: u64) {
+        loop {
+            // Created before the check, so the delivery finished in between is not missed
+            let finished = self.finished.notified();
+            match self.oldest() {
+                Some(oldest) if oldest + crate::models::RESTART_OVERLAP_BLOCKS < block_height => {
+                    finished.await
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Deliveries sent in the background, so slow receivers do not stop the indexing.
+/// The ones which failed all the attempts are stored to `webhook_dead_letters`.
+/// The same event may be delivered more than once, the receivers deduplicate by `event_index`.
+/// The queue lives in memory, so it never gets further behind than the blocks replayed after a restart
+pub(crate) struct DeliveryQueue {
+    sender: tokio::sync::mpsc::Sender<Delivery>,
+    pending: Arc<PendingDeliveries>,
+}
+
+impl DeliveryQueue {
+    pub(crate) fn new(
+        config: &crate::configs::WebhooksConfig,
+        dead_letters: Option<sqlx::PgPool>,
+    ) -> anyhow::Result<Self> {
+        let deliverer = Deliverer {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(config.webhook_timeout_secs))
+                .build()?,
+            max_attempts: config.webhook_max_attempts,
+            dead_letters,
+        };
+        let pending = Arc::new(PendingDeliveries::default());
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(config.webhook_queue_size);
+        let concurrency = config.webhook_concurrency;
+        let finished = pending.clone();
+        tokio::spawn(async move {
+            let deliverer = &deliverer;
+            let finished = &finished;
+            tokio_stream::wrappers::ReceiverStream::new(receiver)
+                .for_each_concurrent(concurrency, |delivery: Delivery| async move {
+                    let block_height = delivery.block_height;
+                    deliverer.process(delivery).await;
+                    finished.finish(block_height);
+                })
+                .await;
+        });
+        Ok(Self { sender, pending })
+    }
+
+    /// Waits if the queue is full, or if its oldest delivery would not be replayed after a restart
+    pub(crate) async fn push(
+        &self,
+        webhook: &Arc<Webhook>,
+        event: &NearBalanceEvent,
+        payload: String,
+    ) -> anyhow::Result<()> {
+        let block_height = event
+            .block_height
+            .to_u64()
+            .ok_or_else(|| anyhow::anyhow!("{} is expected to be u64", event.block_height))?;
+        self.pending.wait_for_replay_window(block_height).await;
+        self.pending.add(block_height);
+        crate::metrics::WEBHOOK_QUEUE_SIZE.inc();
+        self.sender
+            .send(Delivery {
+                webhook: webhook.clone(),
+                block_height,
+                event_index: event.event_index.clone(),
+                affected_account_id: event.affected_account_id.clone(),
+                payload,
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("Webhook deliveries have stopped"))
+    }
+}
+
+/// POSTs the events of the watched accounts to their webhooks
+pub(crate) struct WebhooksSink {
+    watchlist: HashMap<String, Vec<Arc<Webhook>>>,
+    queue: DeliveryQueue,
+}
+
+impl WebhooksSink {
+    pub(crate) fn new(
+        config: &crate::configs::WebhooksConfig,
+        dead_letters: Option<sqlx::PgPool>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            watchlist: load_watchlist(&config.webhooks_watchlist)?,
+            queue: DeliveryQueue::new(config, dead_letters)?,
+        })
+    }
+}
+
+fn load_watchlist(path: &std::path::Path) -> anyhow::Result<HashMap<String, Vec<Arc<Webhook>>>> {
+    let webhooks: Vec<Webhook> = serde_json::from_str(&std::fs::read_to_string(path)?)
+        .map_err(|err| anyhow::anyhow!("Failed to parse {}: {}", path.display(), err))?;
+    let mut watchlist: HashMap<String, Vec<Arc<Webhook>>> = HashMap::new();
+    for webhook in webhooks.into_iter().map(Arc::new) {
+        for account_id in &webhook.accounts {
+            watchlist
+                .entry(account_id.clone())
+                .or_default()
+                .push(webhook.clone());
+        }
+    }
+    Ok(watchlist)
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::EventSink for WebhooksSink {
+    fn name(&self) -> &'static str {
+        "webhooks"
+    }
+
+    async fn store_block(
+        &self,
+        _block_header: &BlockHeaderView,
+        events: &[NearBalanceEvent],
+    ) -> anyhow::Result<()> {
+        for event in events {
+            if event.delta_nonstaked_amount.is_zero() && event.delta_staked_amount.is_zero() {
+                continue;
+            }
+            let webhooks = match self.watchlist.get(&event.affected_account_id) {
+                Some(webhooks) => webhooks,
+                None => continue,
+            };
+            let payload = serde_json::to_string(&EventMessage::from(event))?;
+            for webhook in webhooks {
+                self.queue.push(webhook, event, payload.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Deliverer {
+    client: reqwest::Client,
+    max_attempts: usize,
+    dead_letters: Option<sqlx::PgPool>,
+}
+
+impl Deliverer {
+    async fn process(&self, delivery: Delivery) {
+        if let Err(err) = self.deliver(&delivery).await {
+            tracing::error!(
+                target: crate::LOGGING_PREFIX,
+                "Webhook {} failed {} times for event {}, giving up: {}",
+                delivery.webhook.url,
+                self.max_attempts,
+                delivery.event_index,
+                err,
+            );
+            crate::metrics::WEBHOOK_DEAD_LETTERS_TOTAL.inc();
+            if let Some(pool) = &self.dead_letters {
+                let dead_letter = WebhookDeadLetter {
+                    url: delivery.webhook.url.clone(),
+                    event_index: delivery.event_index.clone(),
+                    affected_account_id: delivery.affected_account_id.clone(),
+                    payload: delivery.payload.clone(),
+                    error: err.to_string(),
+                    attempts: self.max_attempts as i32,
+                };
+                if let Err(err) = crate::models::chunked_insert(
+                    pool,
+                    std::slice::from_ref(&dead_letter),
+                    crate::RETRY_COUNT,
+                )
+                .await
+                {
+                    tracing::error!(
+                        target: crate::LOGGING_PREFIX,
+                        "Failed to store the dead letter {:#?}: {}",
+                        dead_letter,
+                        err,
+                    );
+                }
+            }
+        } else {
+            crate::metrics::WEBHOOK_DELIVERED_TOTAL.inc();
+        }
+        crate::metrics::WEBHOOK_QUEUE_SIZE.dec();
+    }
+
+    async fn deliver(&self, delivery: &Delivery) -> anyhow::Result<()> {
+        let signature = sign(&delivery.webhook.secret, &delivery.payload);
+        let mut interval = crate::INTERVAL;
+        let mut retry_attempt = 0usize;
+
+        loop {
+            retry_attempt += 1;
+            let result = self
+                .client
+                .post(&delivery.webhook.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header("X-Signature-256", format!("sha256={}", signature))
+                .body(delivery.payload.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            match result {
+                Ok(_) => return Ok(()),
+                Err(err) => {
+                    crate::metrics::WEBHOOK_FAILED_ATTEMPTS_TOTAL.inc();
+                    if retry_attempt >= self.max_attempts {
+                        return Err(err.into());
+                    }
+                    tracing::warn!(
+                        target: crate::LOGGING_PREFIX,
+                        "Webhook {} failed for event {}: {}\n Retrying in {} milliseconds...",
+                        delivery.webhook.url,
+                        delivery.event_index,
+                        err,
+                        interval.as_millis(),
+                    );
+                    tokio::time::sleep(interval).await;
+                    if interval < crate::MAX_DELAY_TIME {
+                        interval *= 2;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Hex-encoded HMAC-SHA256 of the payload, sent in `X-Signature-256` header as `sha256=<signature>`
+fn sign(secret: &str, payload: &str) -> String {
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+    use super::*;
+    use crate::sinks::EventSink;
+    use crate::test_utils::{balance_event, block_header, test_pool};
+
+    #[derive(Default)]
+    struct Receiver {
+        failures_left: usize,
+        received: Vec<(String, String)>,
+    }
+
+    type SharedReceiver = Arc<std::sync::Mutex<Receiver>>;
+
+    async fn receive(
+        receiver: web::Data<SharedReceiver>,
+        request: HttpRequest,
+        body: String,
+    ) -> HttpResponse {
+        let mut receiver = receiver.lock().unwrap();
+        if receiver.failures_left > 0 {
+            receiver.failures_left -= 1;
+            return HttpResponse::InternalServerError().finish();
+        }
+        let signature = request
+            .headers()
+            .get("X-Signature-256")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        receiver.received.push((signature, body));
+        HttpResponse::Ok().finish()
+    }
+
+    fn start_receiver(failures: usize) -> (String, SharedReceiver) {
+        let receiver: SharedReceiver = Arc::new(std::sync::Mutex::new(Receiver {
+            failures_left: failures,
+            ..Default::default()
+        }));
+        let data = web::Data::new(receiver.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/hook", web::post().to(receive))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/hook", server.addrs()[0]);
+        tokio::spawn(server.run());
+        (url, receiver)
+    }
+
+    fn config(watchlist: &std::path::Path) -> crate::configs::WebhooksConfig {
+        crate::configs::WebhooksConfig {
+            webhooks_watchlist: watchlist.to_path_buf(),
+            webhook_max_attempts: 3,
+            webhook_timeout_secs: 5,
+            webhook_concurrency: 4,
+            webhook_queue_size: 100,
+        }
+    }
+
+    fn delivery(url: String, payload: &str) -> Delivery {
+        Delivery {
+            webhook: Arc::new(Webhook {
+                url,
+                secret: "secret".to_string(),
+                accounts: vec![],
+            }),
+            block_height: 1,
+            event_index: 1.into(),
+            affected_account_id: "alice.near".to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn posts_signed_events_of_watched_accounts() {
+        let (url, receiver) = start_receiver(1);
+        let dir = tempfile::tempdir().unwrap();
+        let watchlist = dir.path().join("webhooks.json");
+        std::fs::write(
+            &watchlist,
+            serde_json::json!([{"url": url, "secret": "secret", "accounts": ["alice.near"]}])
+                .to_string(),
+        )
+        .unwrap();
+        let sink = WebhooksSink::new(&config(&watchlist), None).unwrap();
+
+        let header = block_header(70_000_000, 1_657_000_000_000_000_000);
+        let events = vec![
+            balance_event(&header, 0, "alice.near", -5, 10),
+            // Not watched
+            balance_event(&header, 1, "bob.near", 5, 15),
+            // Nothing has changed
+            balance_event(&header, 2, "alice.near", 0, 10),
+        ];
+        sink.store_block(&header, &events).await.unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while receiver.lock().unwrap().received.is_empty() && std::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        let received = &receiver.lock().unwrap().received;
+        let expected_payload = serde_json::to_string(&EventMessage::from(&events[0])).unwrap();
+        assert_eq!(
+            received,
+            &vec![(
+                format!("sha256={}", sign("secret", &expected_payload)),
+                expected_payload
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let deliverer = Deliverer {
+            client: reqwest::Client::new(),
+            max_attempts: 3,
+            dead_letters: None,
+        };
+
+        let (url, receiver) = start_receiver(2);
+        deliverer.deliver(&delivery(url, "{}")).await.unwrap();
+        assert_eq!(receiver.lock().unwrap().received.len(), 1);
+
+        let (url, receiver) = start_receiver(3);
+        assert!(deliverer.deliver(&delivery(url, "{}")).await.is_err());
+        assert!(receiver.lock().unwrap().received.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn stores_dead_letters() {
+        let (pool, _schema) = test_pool().await;
+        let deliverer = Deliverer {
+            client: reqwest::Client::new(),
+            max_attempts: 1,
+            dead_letters: Some(pool.clone()),
+        };
+        let (url, _receiver) = start_receiver(1);
+        deliverer.process(delivery(url.clone(), "{}")).await;
+
+        let dead_letter: (String, String, i32) = sqlx::query_as(
+            "SELECT affected_account_id, payload, attempts FROM webhook_dead_letters WHERE url = $1",
+        )
+        .bind(&url)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(dead_letter, ("alice.near".to_string(), "{}".to_string(), 1));
+    }
+
+    #[tokio::test]
+    async fn waits_for_deliveries_out_of_replay_window() {
+        let pending = Arc::new(PendingDeliveries::default());
+        pending.add(1);
+        // Block 1 is still replayed after the restart from block 1001
+        pending.wait_for_replay_window(1001).await;
+
+        let waiting = tokio::spawn({
+            let pending = pending.clone();
+            async move { pending.wait_for_replay_window(1002).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        pending.finish(1);
+        tokio::time::timeout(std::time::Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Helpers shared by the tests of different modules
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix_web::{web, App, HttpResponse, HttpServer};
+use near_lake_framework::near_indexer_primitives::views::BlockHeaderView;
+use near_primitives::types::BlockId;
+
+use crate::models::balance_changes::NearBalanceEvent;
+
+/// Header of the block from `validators_reward` fixture with the given height and timestamp
+pub(crate) fn block_header(height: u64, timestamp: u64) -> BlockHeaderView {
+    let fixture: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/balance_changes/validators_reward.json"
+        ))
+        .expect("Failed to read fixture"),
+    )
+    .expect("Failed to parse fixture");
+    let mut header: BlockHeaderView =
+        serde_json::from_value(fixture["streamer_message"]["block"]["header"].clone())
+            .expect("Failed to parse block header");
+    header.height = height;
+    header.prev_height = Some(height - 1);
+    header.timestamp = timestamp;
+    header.timestamp_nanosec = timestamp;
+    header
+}
+
+/// Event with `event_index` computed the same way the indexer does it
+pub(crate) fn balance_event(
+    block_header: &BlockHeaderView,
+    index_in_block: u128,
+    affected_account_id: &str,
+    delta_nonstaked_amount: i128,
+    absolute_nonstaked_amount: u128,
+) -> NearBalanceEvent {
+    NearBalanceEvent {
+        event_index: (u128::from(block_header.timestamp) * 100_000_000 * 100_000_000
+            + index_in_block)
+            .to_string()
+            .parse()
+            .unwrap(),
+        block_timestamp: block_header.timestamp.into(),
+        block_height: block_header.height.into(),
+        receipt_id: None,
+        transaction_hash: None,
+        affected_account_id: affected_account_id.to_string(),
+        involved_account_id: None,
+        direction: if delta_nonstaked_amount < 0 {
+            "OUTBOUND"
+        } else {
+            "INBOUND"
+        }
+        .to_string(),
+        cause: "RECEIPT".to_string(),
+        status: "SUCCESS".to_string(),
+        delta_nonstaked_amount: delta_nonstaked_amount.to_string().parse().unwrap(),
+        absolute_nonstaked_amount: absolute_nonstaked_amount.to_string().parse().unwrap(),
+        delta_staked_amount: 0.into(),
+        absolute_staked_amount: 0.into(),
+        cause_payload: None,
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub(crate) struct RpcAccount {
+    pub amount: String,
+    pub locked: String,
+}
+
+/// Accounts the mock RPC knows, and the only block it knows them at
+struct MockRpcState {
+    block_id: serde_json::Value,
+    accounts: HashMap<String, RpcAccount>,
+}
+
+// Answers `query` requests with `view_account` the same way the archival node does
+async fn mock_view_account(
+    state: web::Data<MockRpcState>,
+    request: web::Json<serde_json::Value>,
+) -> HttpResponse {
+    let params = &request["params"];
+    let account_id = params["account_id"].as_str().unwrap_or_default();
+    // `block_id` is either the hash or the height
+    let block_hash = match &params["block_id"] {
+        serde_json::Value::String(hash) => hash.clone(),
+        _ => "11111111111111111111111111111111".to_string(),
+    };
+    // At any other block the accounts do not exist, so the wrong lookup breaks the expected balances
+    let account = if params["block_id"] == state.block_id {
+        state.accounts.get(account_id)
+    } else {
+        None
+    };
+
+    let response = match account {
+        Some(account) => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": {
+                "amount": account.amount,
+                "locked": account.locked,
+                "code_hash": "11111111111111111111111111111111",
+                "storage_usage": 182,
+                "storage_paid_at": 0,
+                "block_height": 0,
+                "block_hash": block_hash,
+            }
+        }),
+        None => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "error": {
+                "name": "HANDLER_ERROR",
+                "cause": {
+                    "name": "UNKNOWN_ACCOUNT",
+                    "info": {
+                        "requested_account_id": account_id,
+                        "block_height": 0,
+                        "block_hash": block_hash,
+                    }
+                },
+                "code": -32000,
+                "message": "Server error",
+                "data": format!("account {} does not exist while viewing", account_id),
+            }
+        }),
+    };
+    HttpResponse::Ok().json(response)
+}
+
+/// Archival RPC which knows only `view_account` of the given accounts, at the given block
+pub(crate) fn start_mock_rpc(
+    block_id: BlockId,
+    accounts: HashMap<String, RpcAccount>,
+) -> near_jsonrpc_client::JsonRpcClient {
+    let state = web::Data::new(MockRpcState {
+        block_id: serde_json::to_value(block_id).expect("Failed to serialize block_id"),
+        accounts,
+    });
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .route("/", web::post().to(mock_view_account))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .expect("Failed to bind mock RPC server");
+    let addr = server.addrs()[0];
+    tokio::spawn(server.run());
+
+    near_jsonrpc_client::JsonRpcClient::connect(format!("http://{}", addr))
+}
+
+/// Schema of `DATABASE_URL` with the empty copies of its tables, dropped at the end of the test
+pub(crate) struct TestSchema {
+    /// `DATABASE_URL` with the schema, for the code which connects by itself
+    pub url: String,
+    name: String,
+}
+
+/// Pool of the new `TestSchema`, so the tests see only their own rows and need no cleanup.
+/// Keep the schema in a named variable, `_` drops it right away.
+/// The tables are copied from `DATABASE_URL` with the migrations applied, the tests using it are
+/// `#[ignore]`d and run with `DATABASE_URL=... cargo test -- --ignored`
+pub(crate) async fn test_pool() -> (sqlx::PgPool, TestSchema) {
+    static NEXT_SCHEMA: AtomicUsize = AtomicUsize::new(0);
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL is required");
+    let name = format!(
+        "test_{}_{}",
+        std::process::id(),
+        NEXT_SCHEMA.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let pool = sqlx::PgPool::connect(&database_url)
+        .await
+        .expect("Failed to connect to DATABASE_URL");
+    let tables: Vec<(String,)> = sqlx::query_as(
+        "SELECT CAST(table_name AS TEXT) FROM information_schema.tables
+        WHERE table_schema = 'public' AND table_type = 'BASE TABLE'",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    sqlx::query(&format!("CREATE SCHEMA {}", name))
+        .execute(&pool)
+        .await
+        .unwrap();
+    for (table,) in tables {
+        sqlx::query(&format!(
+            "CREATE TABLE {}.{} (LIKE public.{} INCLUDING ALL)",
+            name, table, table
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    pool.close().await;
+
+    let url = format!(
+        "{}{}options[search_path]={}",
+        database_url,
+        if database_url.contains('?') { '&' } else { '?' },
+        name
+    );
+    let pool = sqlx::PgPool::connect(&url).await.unwrap();
+    (pool, TestSchema { url, name })
+}
+
+impl Drop for TestSchema {
+    fn drop(&mut self) {
+        // Drop can't wait for the query in the runtime of the test, so it gets its own one
+        let name = self.name.clone();
+        let result = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(async move {
+                    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+                    sqlx::query(&format!("DROP SCHEMA {} CASCADE", name))
+                        .execute(&pool)
+                        .await?;
+                    anyhow::Ok(())
+                })
+        })
+        .join();
+        if !matches!(result, Ok(Ok(()))) {
+            eprintln!("Failed to drop the test schema {}", self.name);
+        }
+    }
+}